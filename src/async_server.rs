@@ -0,0 +1,315 @@
+use crate::config::ServerConfig;
+use crate::framing::encode_frame;
+use crate::handler::{error_response, AddHandler, ConnectionContext, ConnectionId, EchoHandler, Handler};
+use crate::message::{ErrorCode, ServerMessage};
+use crate::rate_limit::{PeerLimits, RateLimiter};
+use crate::server::{bind_listener, next_accept_backoff};
+use crate::session::{goodbye, response_to, Session};
+use log::{error, info, warn};
+use std::{
+    future::{self, Future},
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::watch,
+    task::JoinSet,
+    time::{self, Instant},
+};
+
+// Server speaking the same protocol as `Server`, with each connection served by a task on
+// the caller's tokio runtime instead of a dedicated worker thread. It uses the same
+// configuration and handlers; `worker_threads`, `accept_queue_size` and
+// `saturation_policy` don't apply since connections don't wait for a worker. Server
+// pushes, publish/subscribe topics, TLS and Unix domain sockets are only offered by
+// `Server`.
+pub struct AsyncServer {
+    listener: std::net::TcpListener, // Listener for incoming connections, registered with tokio by `run`
+    shutdown: watch::Sender<bool>,   // Set to true by `stop`, which every task watches
+    active_clients: Arc<AtomicUsize>, // Number of clients currently being served
+    next_connection_id: AtomicU64,   // Id given to the next connection
+    handlers: Vec<Arc<dyn Handler>>, // Request handlers, most recently added first
+    peer_limits: Option<Arc<PeerLimits>>, // Rate limits shared by connections from the same IP
+    config: ServerConfig,            // Settings the server was created with
+}
+
+impl AsyncServer {
+    // Creates a new server on any available port
+    pub fn new() -> Result<Self, io::Error> {
+        Self::new_with_port(0)
+    }
+
+    // Creates a new server on the specified port
+    pub fn new_with_port(port: u16) -> Result<Self, io::Error> {
+        Self::with_config(ServerConfig::default().port(port))
+    }
+
+    // Creates a new server with the given settings. The listener is bound at once, so the
+    // port is known before `run` is awaited.
+    pub fn with_config(config: ServerConfig) -> Result<Self, io::Error> {
+        if config.read_buffer_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Read buffer size must be non-zero"));
+        }
+        config.validate_rate_limits()?;
+        #[cfg(feature = "tls")]
+        if config.tls.is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "TLS is only supported by Server"));
+        }
+        #[cfg(unix)]
+        if config.unix_socket.is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Unix domain sockets are only supported by Server"));
+        }
+
+        let listener = bind_listener(&config)?;
+        listener.set_nonblocking(true)?; // Required by tokio
+        Ok(Self {
+            listener,
+            shutdown: watch::channel(false).0,
+            active_clients: Arc::new(AtomicUsize::new(0)),
+            next_connection_id: AtomicU64::new(1),
+            handlers: vec![Arc::new(EchoHandler), Arc::new(AddHandler)],
+            peer_limits: PeerLimits::from_config(&config),
+            config,
+        })
+    }
+
+    // Adds a request handler. Handlers added later are consulted first, as with `Server`.
+    pub fn with_handler<H: Handler + 'static>(mut self, handler: H) -> Self {
+        self.handlers.insert(0, Arc::new(handler));
+        self
+    }
+
+    // Accepts and serves clients until the server is stopped. Must be awaited within a
+    // tokio runtime; returns once every connection has been closed.
+    pub async fn run(&self) -> io::Result<()> {
+        let listener = TcpListener::from_std(self.listener.try_clone()?)?;
+        let mut shutdown = self.shutdown.subscribe();
+        let mut clients = JoinSet::new();
+
+        // A `stop` issued before `run` is reached is seen here straight away
+        let mut accept_backoff = None;
+        while !*shutdown.borrow_and_update() {
+            tokio::select! {
+                changed = shutdown.changed() => {
+                    if changed.is_err() {
+                        break; // The server was dropped
+                    }
+                }
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, addr)) => {
+                        accept_backoff = None;
+
+                        // New client connection accepted
+                        info!("New client connected: {}", addr);
+
+                        // Turn the client away if the server is already serving as many as it may
+                        if let Some(max_clients) = self.config.max_clients {
+                            if self.active_clients.load(Ordering::SeqCst) >= max_clients {
+                                warn!("Rejecting client {}: {} clients already connected", addr, max_clients);
+                                let detail = format!("Server is serving its maximum of {} clients", max_clients);
+                                clients.spawn(reject_client(stream, detail, self.config.write_timeout));
+                                continue;
+                            }
+                        }
+
+                        clients.spawn(self.client_task(stream));
+                    }
+                    Err(e) => {
+                        // Error occurred while accepting a connection. Errors such as running
+                        // out of file descriptors persist for a while, so wait before trying
+                        // again, unless the server stops in the meantime.
+                        let backoff = next_accept_backoff(accept_backoff);
+                        warn!("Error accepting connection, retrying in {:?}: {}", backoff, e);
+                        tokio::select! {
+                            _ = shutdown.changed() => {}
+                            _ = time::sleep(backoff) => {}
+                        }
+                        accept_backoff = Some(backoff);
+                    }
+                },
+            }
+
+            // Release the results of connections that have already closed
+            while clients.try_join_next().is_some() {}
+        }
+
+        // Connections watch the shutdown signal too, so they close promptly
+        while clients.join_next().await.is_some() {}
+        info!("Server stopped.");
+        Ok(())
+    }
+
+    // Builds the task that serves a client
+    fn client_task(&self, stream: TcpStream) -> impl Future<Output = ()> + Send + 'static {
+        let connection_id = self.next_connection_id.fetch_add(1, Ordering::Relaxed);
+        let shutdown = self.shutdown.subscribe();
+        let active_clients = Arc::clone(&self.active_clients);
+        let handlers = self.handlers.clone();
+        let peer_limits = self.peer_limits.clone();
+        let config = self.config.clone();
+
+        active_clients.fetch_add(1, Ordering::SeqCst);
+        async move {
+            if let Err(e) = serve_client(stream, connection_id, handlers, peer_limits, config, shutdown).await {
+                error!("Error handling client: {}", e);
+            }
+            active_clients.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    // Stops the server and disconnects all clients. May be called from any thread, inside
+    // or outside the runtime.
+    pub fn stop(&self) {
+        self.shutdown.send_replace(true);
+        info!("Server shutting down...");
+    }
+
+    // Number of connections currently being served
+    pub fn connection_count(&self) -> usize {
+        self.active_clients.load(Ordering::SeqCst)
+    }
+
+    // Retrieves the port the server is listening on
+    pub fn get_port(&self) -> Result<u16, io::Error> {
+        self.listener.local_addr().map(|addr| addr.port())
+    }
+
+    // Retrieves the address the server is listening on
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.listener.local_addr()
+    }
+
+    // Retrieves the settings the server was created with
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+}
+
+// Reads requests from a client and answers them until the client disconnects, a deadline
+// passes or the server stops. Deadlines follow the same rules as the threaded server.
+async fn serve_client(
+    mut stream: TcpStream,
+    connection_id: ConnectionId,
+    handlers: Vec<Arc<dyn Handler>>,
+    peer_limits: Option<Arc<PeerLimits>>,
+    config: ServerConfig,
+    mut shutdown: watch::Receiver<bool>,
+) -> io::Result<()> {
+    let context = ConnectionContext {
+        connection_id,
+        peer_addr: stream.peer_addr()?.into(),
+        local_addr: stream.local_addr()?.into(),
+    };
+    let rate_limiter = RateLimiter::new(&config, peer_limits.as_ref(), &context.peer_addr);
+    let mut session = Session::new(context, handlers, &config, rate_limiter);
+    let mut buffer = vec![0; config.read_buffer_size]; // Buffer to store incoming data
+    let mut last_activity = Instant::now(); // When the client last sent data
+    let mut partial_since: Option<Instant> = None; // When the buffered partial message started
+
+    while !*shutdown.borrow_and_update() {
+        // A partially received message must be completed within the read timeout, and a
+        // silent client is dropped after the idle timeout, and a client that hasn't
+        // authenticated by its deadline is dropped whatever it sends
+        let deadline = match partial_since {
+            Some(started) => config.read_timeout.map(|timeout| (started + timeout, "read")),
+            None => config.idle_timeout.map(|timeout| (last_activity + timeout, "idle")),
+        };
+        let auth_deadline = session.auth_deadline().map(|deadline| (Instant::from_std(deadline), "authentication"));
+        let deadline = deadline.into_iter().chain(auth_deadline).min_by_key(|&(deadline, _)| deadline);
+        let expired = async {
+            match deadline {
+                Some((deadline, _)) => time::sleep_until(deadline).await,
+                None => future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            changed = shutdown.changed() => {
+                if changed.is_err() {
+                    break; // The server was dropped
+                }
+            }
+            _ = expired => {
+                let kind = deadline.map_or("", |(_, kind)| kind);
+                info!("Closing connection from {}: {} timeout expired", session.context().peer_addr, kind);
+                break;
+            }
+            read = stream.read(&mut buffer) => match read {
+                Ok(0) => {
+                    // Client disconnected
+                    info!("Client disconnected.");
+                    break;
+                }
+                Ok(bytes_read) => {
+                    // Successfully read data from the client
+                    info!("Received {} bytes from client", bytes_read);
+                    last_activity = Instant::now();
+
+                    // Answer every complete message received so far
+                    let received = session.receive(&buffer[..bytes_read]);
+                    send_responses(&mut stream, &received.responses, config.write_timeout).await?;
+                    if received.close {
+                        break;
+                    }
+
+                    // Start the read timeout when a message is left incomplete
+                    partial_since = if session.has_partial_message() {
+                        partial_since.or(Some(last_activity))
+                    } else {
+                        None
+                    };
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    // Error occurred while reading from the client
+                    error!("Error reading from client: {}", e);
+                    break;
+                }
+            },
+        }
+    }
+
+    // Tell the client the server is going away; requests already answered were complete
+    if *shutdown.borrow() {
+        if let Err(e) = write_with_timeout(&mut stream, &encode_frame(&goodbye()), config.write_timeout).await {
+            info!("Could not say goodbye to {}: {}", session.context().peer_addr, e);
+        }
+    }
+    stream.shutdown().await
+}
+
+// Sends responses to the client, coalesced into a single write
+async fn send_responses(stream: &mut TcpStream, responses: &[ServerMessage], timeout: Option<Duration>) -> io::Result<()> {
+    if responses.is_empty() {
+        return Ok(());
+    }
+
+    let buffer: Vec<u8> = responses.iter().flat_map(encode_frame).collect();
+    write_with_timeout(stream, &buffer, timeout).await?;
+    info!("Sent {} responses", responses.len());
+    Ok(())
+}
+
+// Writes all of `buffer`, failing with `TimedOut` if it takes longer than the write timeout
+async fn write_with_timeout(stream: &mut TcpStream, buffer: &[u8], timeout: Option<Duration>) -> io::Result<()> {
+    match timeout {
+        Some(timeout) => time::timeout(timeout, stream.write_all(buffer))
+            .await
+            .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "Write timed out"))),
+        None => stream.write_all(buffer).await,
+    }
+}
+
+// Tells a client the server is too busy to serve it and closes its connection
+async fn reject_client(mut stream: TcpStream, detail: String, timeout: Option<Duration>) {
+    let response = encode_frame(&response_to(0, error_response(ErrorCode::ServerBusy, detail)));
+    if let Err(e) = write_with_timeout(&mut stream, &response, timeout).await {
+        warn!("Error rejecting client: {}", e);
+    }
+    let _ = stream.shutdown().await;
+}
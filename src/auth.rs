@@ -0,0 +1,142 @@
+use crate::config::ServerConfig;
+use crate::handler::error_response;
+use crate::message::{auth_request, server_message, AuthRequest, AuthResponse, ErrorCode};
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use sha2::Sha256;
+use std::{fmt, time::Instant};
+
+// Size of the random challenges issued for HMAC authentication
+const CHALLENGE_SIZE: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// How clients prove they may use a server, set with `ServerConfig::auth`
+#[derive(Clone, PartialEq, Eq)]
+pub enum AuthMethod {
+    // Clients send the pre-shared token itself
+    Token(Vec<u8>),
+    // Clients answer a random challenge with its HMAC-SHA256 keyed with the shared secret,
+    // so the secret never crosses the connection
+    Hmac(Vec<u8>),
+}
+
+// Keeps the secret out of logs
+impl fmt::Debug for AuthMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthMethod::Token(_) => f.write_str("Token(..)"),
+            AuthMethod::Hmac(_) => f.write_str("Hmac(..)"),
+        }
+    }
+}
+
+// Computes the response to an HMAC challenge, as a client of a server using
+// `AuthMethod::Hmac` sends it in `AuthRequest::challenge_response`
+pub fn challenge_response(secret: &[u8], challenge: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(challenge);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// Authentication progress of one connection
+pub(crate) struct AuthState {
+    method: AuthMethod,                 // What the client must prove
+    challenge: Option<Vec<u8>>,         // Challenge issued and not yet answered
+    authenticated: bool,                // Set once the client has authenticated
+    failures: u32,                      // Failed attempts so far
+    max_failures: u32,                  // Failed attempts after which the connection is closed
+    deadline: Option<Instant>,          // When the client must have authenticated by
+}
+
+impl AuthState {
+    // Creates the state of a new connection, or `None` if the server doesn't require
+    // authentication
+    pub fn new(config: &ServerConfig) -> Option<Self> {
+        config.auth.clone().map(|method| AuthState {
+            method,
+            challenge: None,
+            authenticated: false,
+            failures: 0,
+            max_failures: config.max_auth_failures.max(1),
+            deadline: config.auth_timeout.map(|timeout| Instant::now() + timeout),
+        })
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    // Returns true once the client has failed as many times as it may
+    pub fn is_exhausted(&self) -> bool {
+        self.failures >= self.max_failures
+    }
+
+    // When the client must have authenticated by, unless it already has
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline.filter(|_| !self.authenticated)
+    }
+
+    // Counts a request refused because the client hasn't authenticated as a failed attempt,
+    // so a client can't keep a connection by sending requests it is refused
+    pub fn refuse(&mut self) -> server_message::Message {
+        self.failures += 1;
+        warn!("Request before authentication ({} of {} failed attempts)", self.failures, self.max_failures);
+        error_response(ErrorCode::Unauthenticated, "Authenticate before sending requests".to_string())
+    }
+
+    // Checks the credentials of an AuthRequest and builds the response to it
+    pub fn authenticate(&mut self, request: &AuthRequest) -> server_message::Message {
+        if self.authenticated {
+            return authenticated();
+        }
+
+        let valid = match (&self.method, &request.credentials) {
+            (AuthMethod::Token(token), Some(auth_request::Credentials::Token(sent))) => constant_time_eq(token, sent),
+            (AuthMethod::Hmac(_), None) => {
+                // Issue a fresh challenge, replacing any unanswered one
+                let mut challenge = vec![0; CHALLENGE_SIZE];
+                if let Err(e) = getrandom::getrandom(&mut challenge) {
+                    warn!("Could not generate an authentication challenge: {}", e);
+                    return error_response(ErrorCode::AuthenticationFailed, "No challenge available".to_string());
+                }
+                self.challenge = Some(challenge.clone());
+                return server_message::Message::AuthResponse(AuthResponse {
+                    authenticated: false,
+                    challenge,
+                });
+            }
+            (AuthMethod::Hmac(secret), Some(auth_request::Credentials::ChallengeResponse(sent))) => {
+                // Each challenge may be answered once, so a recorded response can't be replayed
+                match self.challenge.take() {
+                    Some(challenge) => constant_time_eq(&challenge_response(secret, &challenge), sent),
+                    None => false,
+                }
+            }
+            _ => false,
+        };
+
+        if valid {
+            info!("Connection authenticated");
+            self.authenticated = true;
+            return authenticated();
+        }
+        self.failures += 1;
+        warn!("Authentication failed ({} of {} attempts)", self.failures, self.max_failures);
+        error_response(ErrorCode::AuthenticationFailed, "Invalid credentials".to_string())
+    }
+}
+
+// Response to an AuthRequest on an authenticated connection, or on a server that doesn't
+// require authentication
+pub(crate) fn authenticated() -> server_message::Message {
+    server_message::Message::AuthResponse(AuthResponse {
+        authenticated: true,
+        challenge: Vec::new(),
+    })
+}
+
+// Compares secrets in time independent of where they differ
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |difference, (x, y)| difference | (x ^ y)) == 0
+}
@@ -0,0 +1,81 @@
+use crate::client::{ClientError, Result};
+use crate::message::{
+    client_message, server_message, AddRequest, EchoMessage, OverflowMode, Ping, Publish, Subscribe, Unsubscribe,
+};
+
+// Reads the result out of a response, handing back a response of the wrong kind
+type Read<T> = Box<dyn FnOnce(server_message::Message) -> std::result::Result<T, server_message::Message>>;
+
+// Typed request shared by `Client` and `SharedClient`: the message to send, and how to
+// read the result out of the response to it. Both clients build their typed methods from
+// these, so they speak the protocol the same way.
+pub(crate) struct Call<T> {
+    message: client_message::Message,
+    read: Read<T>,
+}
+
+impl<T> Call<T> {
+    fn new(
+        message: client_message::Message,
+        read: impl FnOnce(server_message::Message) -> std::result::Result<T, server_message::Message> + 'static,
+    ) -> Self {
+        Call { message, read: Box::new(read) }
+    }
+
+    // Sends the message with `request` and reads the result from the response, failing
+    // with `UnexpectedResponse` if it isn't the one the request calls for
+    pub fn send(self, request: impl FnOnce(client_message::Message) -> Result<server_message::Message>) -> Result<T> {
+        (self.read)(request(self.message)?).map_err(|other| ClientError::UnexpectedResponse(Some(other)))
+    }
+}
+
+// Ping carrying the payload, answered by a Pong with the same payload
+pub(crate) fn ping(payload: u64) -> Call<()> {
+    Call::new(client_message::Message::Ping(Ping { payload }), move |response| match response {
+        server_message::Message::Pong(pong) if pong.payload == payload => Ok(()),
+        other => Err(other),
+    })
+}
+
+// EchoMessage, answered with the echoed content
+pub(crate) fn echo(content: String) -> Call<String> {
+    Call::new(client_message::Message::EchoMessage(EchoMessage { content }), |response| match response {
+        server_message::Message::EchoMessage(echo) => Ok(echo.content),
+        other => Err(other),
+    })
+}
+
+// AddRequest, answered with the sum
+pub(crate) fn add(a: i32, b: i32, mode: OverflowMode) -> Call<i32> {
+    let message = client_message::Message::AddRequest(AddRequest { a, b, mode: mode.into() });
+    Call::new(message, |response| match response {
+        server_message::Message::AddResponse(add) => Ok(add.result),
+        other => Err(other),
+    })
+}
+
+// Subscribe, answered with every topic the connection is subscribed to
+pub(crate) fn subscribe(topic: String) -> Call<Vec<String>> {
+    Call::new(client_message::Message::Subscribe(Subscribe { topic }), subscriptions)
+}
+
+// Unsubscribe, answered with the topics the connection is left subscribed to
+pub(crate) fn unsubscribe(topic: String) -> Call<Vec<String>> {
+    Call::new(client_message::Message::Unsubscribe(Unsubscribe { topic }), subscriptions)
+}
+
+// Publish, answered with how many subscribers the event was delivered to
+pub(crate) fn publish(topic: String, payload: Vec<u8>) -> Call<u32> {
+    Call::new(client_message::Message::Publish(Publish { topic, payload }), |response| match response {
+        server_message::Message::PublishResponse(response) => Ok(response.subscribers),
+        other => Err(other),
+    })
+}
+
+// Reads the topics out of the Subscriptions answering a Subscribe or Unsubscribe
+fn subscriptions(response: server_message::Message) -> std::result::Result<Vec<String>, server_message::Message> {
+    match response {
+        server_message::Message::Subscriptions(subscriptions) => Ok(subscriptions.topics),
+        other => Err(other),
+    }
+}
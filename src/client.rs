@@ -0,0 +1,676 @@
+use crate::auth::{self, AuthMethod};
+use crate::calls::{self, Call};
+use crate::config::ClientConfig;
+use crate::failover::{self, EndpointHealth, Endpoints};
+use crate::framing::{encode_frame, FrameBuffer, FrameTooLarge};
+use crate::message::{
+    auth_request, client_message, server_message, AuthRequest, AuthResponse, ClientMessage, ErrorCode, ErrorResponse,
+    OverflowMode, ServerMessage,
+};
+use crate::shared_client::SharedClient;
+use crate::transport::{Reader, Stream, Writer};
+use log::{debug, info, warn};
+use prost::Message;
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt,
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, ToSocketAddrs},
+    thread,
+    time::{Duration, Instant},
+};
+#[cfg(unix)]
+use std::{os::unix::net::UnixStream, path::PathBuf};
+
+// Size of the buffer data from the server is read into
+pub(crate) const READ_BUFFER_SIZE: usize = 8 * 1024;
+
+// Messages a client holds over for later receives before it starts dropping them
+pub(crate) const MAX_HELD_MESSAGES: usize = 1024;
+
+// Where a client connects to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    // Host name or IP address and port. Names are resolved on every connection attempt
+    // and each resolved address is tried in turn.
+    Tcp { host: String, port: u16 },
+    // Path of a server's Unix domain socket
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Endpoint {
+    pub fn tcp(host: impl Into<String>, port: u16) -> Self {
+        Endpoint::Tcp { host: host.into(), port }
+    }
+
+    #[cfg(unix)]
+    pub fn unix(path: impl Into<PathBuf>) -> Self {
+        Endpoint::Unix(path.into())
+    }
+}
+
+impl From<SocketAddr> for Endpoint {
+    fn from(address: SocketAddr) -> Self {
+        Endpoint::tcp(address.ip().to_string(), address.port())
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Tcp { host, port } if host.contains(':') => write!(f, "[{}]:{}", host, port),
+            Endpoint::Tcp { host, port } => write!(f, "{}:{}", host, port),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+// Errors returned by `Client`
+#[derive(Debug)]
+pub enum ClientError {
+    // The client isn't connected, either never or since the connection failed
+    NotConnected,
+    // The server closed the connection
+    Disconnected,
+    // A read or write took longer than its timeout. After a read the connection stays
+    // usable, and a response that arrives later is returned by the next receive. After a
+    // write the connection is closed, as part of the message may already have been sent.
+    TimedOut,
+    // The server sent something that isn't a valid message. The connection is closed, as
+    // it can't be brought back in sync.
+    Protocol(String),
+    // The server answered the request with an error. The connection stays usable.
+    Server(ErrorResponse),
+    // The server answered the request with a message of the wrong kind, or with none
+    UnexpectedResponse(Option<server_message::Message>),
+    // The connection could not be established or failed
+    Io(io::Error),
+}
+
+impl ClientError {
+    // Returns true for errors that may go away by connecting again
+    fn is_connection_error(&self) -> bool {
+        matches!(
+            self,
+            ClientError::NotConnected | ClientError::Disconnected | ClientError::TimedOut | ClientError::Io(_)
+        )
+    }
+
+    // Code of an error the server answered with
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        match self {
+            ClientError::Server(error) => Some(error.code()),
+            _ => None,
+        }
+    }
+
+    // How long the server asked the client to wait before sending again, for requests
+    // refused by a rate limit
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ClientError::Server(error) if error.code() == ErrorCode::RateLimited => {
+                Some(Duration::from_millis(error.retry_after_ms.into()))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::NotConnected => f.write_str("Not connected"),
+            ClientError::Disconnected => f.write_str("Server disconnected"),
+            ClientError::TimedOut => f.write_str("Timed out"),
+            ClientError::Protocol(detail) => write!(f, "Protocol error: {}", detail),
+            ClientError::Server(error) => write!(f, "Server error {:?}: {}", error.code(), error.detail),
+            ClientError::UnexpectedResponse(Some(message)) => write!(f, "Unexpected response: {:?}", message),
+            ClientError::UnexpectedResponse(None) => f.write_str("Empty response"),
+            ClientError::Io(e) => write!(f, "Connection error: {}", e),
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ClientError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            // Timeouts surface as either kind depending on the platform
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ClientError::TimedOut,
+            io::ErrorKind::UnexpectedEof => ClientError::Disconnected,
+            _ => ClientError::Io(error),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+// An open connection to the server, split so reads and writes can happen on different
+// threads
+pub(crate) struct Connection {
+    pub stream: Stream, // Socket, used to apply timeouts and shut the connection down
+    pub reader: Reader, // Messages from the server are read from here
+    pub writer: Writer, // Messages to the server are written here
+}
+
+// Client for the server's protocol. Requests are tagged with increasing request ids, and
+// responses to other requests that arrive while waiting for a particular one are held
+// until asked for, so requests can be pipelined. Up to 1024 messages are held; beyond that
+// the oldest are dropped with a warning, so events of a subscription nobody receives from
+// can't use up memory.
+pub struct Client {
+    endpoints: Endpoints,               // Where `connect` connects to, with the health of each
+    config: ClientConfig,               // Timeouts, limits, TLS and reconnect settings
+    connection: Option<Connection>,     // Open connection, if any
+    frames: FrameBuffer,                // Reassembles frames received from the server
+    buffer: Vec<u8>,                    // Buffer reads from the server land in
+    next_request_id: u64,               // Id assigned to the next request sent with `send_request`
+    pending: VecDeque<ServerMessage>,   // Messages received while awaiting a different request id
+    dropping: bool,                     // Whether the last held message pushed out an older one
+    lost: bool,                         // Set when the connection failed rather than being closed
+    reconnecting: bool,                 // Set while a lost connection is being re-established
+    auth: Option<AuthMethod>,           // Credentials the connection was authenticated with
+    subscriptions: Vec<String>,         // Topics the connection is subscribed to
+}
+
+impl Client {
+    // Creates a client for the endpoint with the default settings. Call `connect` to
+    // open the connection.
+    pub fn new(endpoint: impl Into<Endpoint>) -> Self {
+        Self::with_config(endpoint, ClientConfig::default())
+    }
+
+    // Creates a client for the endpoint with the given settings
+    pub fn with_config(endpoint: impl Into<Endpoint>, config: ClientConfig) -> Self {
+        Self::with_endpoints([endpoint.into()], config)
+    }
+
+    // Creates a client for redundant servers with the given settings. Connections are
+    // made to the first endpoint that accepts one, trying endpoints that failed last after
+    // the others, so a lost connection fails over to the next server. Panics if there are
+    // no endpoints.
+    pub fn with_endpoints(endpoints: impl IntoIterator<Item = Endpoint>, config: ClientConfig) -> Self {
+        Client {
+            endpoints: Endpoints::new(endpoints.into_iter().collect()),
+            frames: FrameBuffer::with_max_frame_size(config.max_message_size),
+            config,
+            connection: None,
+            buffer: vec![0; READ_BUFFER_SIZE],
+            next_request_id: 1,
+            pending: VecDeque::new(),
+            dropping: false,
+            lost: false,
+            reconnecting: false,
+            auth: None,
+            subscriptions: Vec::new(),
+        }
+    }
+
+    // Connects to the endpoint, replacing any open connection. The new connection starts
+    // unauthenticated and without subscriptions.
+    pub fn connect(&mut self) -> Result<()> {
+        self.auth = None;
+        self.subscriptions.clear();
+        self.open()
+    }
+
+    // Opens a connection to the first endpoint that accepts one, in order of health
+    fn open(&mut self) -> Result<()> {
+        self.connection = None;
+        let mut last_error = ClientError::NotConnected;
+        for index in self.endpoints.connection_order() {
+            let endpoint = self.endpoints.get(index);
+            match self.open_endpoint(endpoint) {
+                Ok(connection) => {
+                    info!("Connected to server at {}", endpoint);
+                    self.endpoints.connected(index);
+                    self.connection = Some(connection);
+                    self.frames = FrameBuffer::with_max_frame_size(self.config.max_message_size);
+                    self.pending.clear();
+                    self.lost = false;
+                    return Ok(());
+                }
+                Err(e) => {
+                    debug!("Could not connect to {}: {}", endpoint, e);
+                    self.endpoints.failed(index, &e);
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    // Opens a connection to one endpoint
+    fn open_endpoint(&self, endpoint: &Endpoint) -> Result<Connection> {
+        let socket = self.open_socket(endpoint)?;
+        socket.set_read_timeout(self.config.read_timeout)?;
+        socket.set_write_timeout(self.config.write_timeout)?;
+        let (reader, writer) = self.split(endpoint, &socket)?;
+        Ok(Connection {
+            stream: socket,
+            reader,
+            writer,
+        })
+    }
+
+    // Connects the socket, racing the addresses a host name resolves to
+    fn open_socket(&self, endpoint: &Endpoint) -> Result<Stream> {
+        match *endpoint {
+            Endpoint::Tcp { ref host, port } => {
+                let addresses: Vec<_> = (host.as_str(), port).to_socket_addrs()?.collect();
+                let attempt_delay = self.config.connection_attempt_delay;
+                let stream = failover::connect_tcp(&addresses, self.config.connect_timeout, attempt_delay)?;
+                stream.set_nodelay(true)?; // Requests are small and latency matters
+                Ok(Stream::Tcp(stream))
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(ref path) => Ok(Stream::Unix(UnixStream::connect(path)?)),
+        }
+    }
+
+    // Builds the halves frames travel over, starting a TLS session if configured
+    fn split(&self, endpoint: &Endpoint, socket: &Stream) -> Result<(Reader, Writer)> {
+        #[cfg(feature = "tls")]
+        if let Some(ref tls) = self.config.tls {
+            let host = match (socket, endpoint) {
+                (Stream::Tcp(_), Endpoint::Tcp { host, .. }) => host,
+                _ => return Err(io::Error::new(io::ErrorKind::Unsupported, "TLS is only supported over TCP").into()),
+            };
+            let server_name = self.config.server_name.as_deref().unwrap_or(host);
+            let (reader, writer) = tls.split(server_name, socket)?;
+            return Ok((Box::new(reader), Box::new(writer)));
+        }
+
+        #[cfg(not(feature = "tls"))]
+        let _ = endpoint; // Only TLS needs the host name
+        Ok((Box::new(socket.try_clone()?), Box::new(socket.try_clone()?)))
+    }
+
+    // Returns true while a connection is open. A connection that failed or that the
+    // server closed is dropped when the failure is noticed, and re-established by the next
+    // send or receive if the client has a reconnect policy.
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    // Re-establishes a lost connection if the reconnect policy allows it, then restores the
+    // authentication and subscriptions of the lost one
+    fn reconnect(&mut self) -> Result<()> {
+        let policy = match self.config.reconnect {
+            Some(ref policy) if self.lost && !self.reconnecting => policy.clone(),
+            _ => return Err(ClientError::NotConnected),
+        };
+
+        self.reconnecting = true;
+        let mut attempts = 0;
+        let result = loop {
+            attempts += 1;
+            match self.open().and_then(|()| self.restore()) {
+                Ok(()) => break Ok(()),
+                Err(e) if e.is_connection_error() && policy.may_retry(attempts) => {
+                    let delay = policy.delay(attempts);
+                    debug!("Reconnect attempt {} failed: {}", attempts, e);
+                    self.lose_connection(&e);
+                    thread::sleep(delay);
+                }
+                Err(e) => break Err(e),
+            }
+        };
+        self.reconnecting = false;
+
+        match result {
+            Ok(()) => {
+                info!("Reconnected to {} after {} attempt(s)", self.endpoints.current(), attempts);
+                policy.notify(self.endpoints.current(), attempts);
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Giving up reconnecting after {} attempt(s): {}", attempts, e);
+                self.lose_connection(&e); // Lets the next send or receive try again
+                Err(e)
+            }
+        }
+    }
+
+    // Authenticates and subscribes a new connection as the lost one was
+    fn restore(&mut self) -> Result<()> {
+        if let Some(method) = self.auth.clone() {
+            self.authenticate(&method)?;
+        }
+        for topic in self.subscriptions.clone() {
+            self.subscribe(topic)?;
+        }
+        Ok(())
+    }
+
+    // Re-establishes a lost connection before it is used, if the client may
+    fn ensure_connected(&mut self) -> Result<()> {
+        if self.connection.is_none() {
+            self.reconnect()?;
+        }
+        Ok(())
+    }
+
+    // Closes the connection
+    pub fn disconnect(&mut self) -> Result<()> {
+        self.lost = false;
+        let connection = self.connection.take().ok_or(ClientError::NotConnected)?;
+        if let Err(e) = connection.stream.shutdown(Shutdown::Both) {
+            // The server may already have closed its end
+            debug!("Error shutting down connection: {}", e);
+        }
+        info!("Disconnected from server at {}", self.endpoints.current());
+        Ok(())
+    }
+
+    // Turns the client into a handle that many threads can share, keeping the connection
+    // with its authentication and subscriptions. Fails with `NotConnected` if there is no
+    // open connection.
+    pub fn into_shared(mut self) -> Result<SharedClient> {
+        let connection = self.connection.take().ok_or(ClientError::NotConnected)?;
+        let endpoint = self.endpoints.current().clone();
+        SharedClient::new(endpoint, &self.config, connection, self.frames, self.next_request_id, self.pending)
+    }
+
+    // Endpoint the client is connected to, or was last connected to
+    pub fn endpoint(&self) -> &Endpoint {
+        self.endpoints.current()
+    }
+
+    // Endpoints the client may connect to, in the order given, with how connections to
+    // each have fared
+    pub fn health(&self) -> &[EndpointHealth] {
+        self.endpoints.health()
+    }
+
+    // Settings the client was created with
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    // Local address of the connection, for TCP connections
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        match self.connection.as_ref().map(|connection| &connection.stream) {
+            Some(Stream::Tcp(stream)) => Ok(stream.local_addr()?),
+            #[cfg(unix)]
+            Some(Stream::Unix(_)) => {
+                Err(io::Error::new(io::ErrorKind::Unsupported, "Connected over a Unix domain socket").into())
+            }
+            None => Err(ClientError::NotConnected),
+        }
+    }
+
+    // Sets how long a receive waits for data before failing with `TimedOut`, `None` to
+    // wait forever. Applies to the current connection only.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        let connection = self.connection.as_ref().ok_or(ClientError::NotConnected)?;
+        Ok(connection.stream.set_read_timeout(timeout)?)
+    }
+
+    // Pings the server and returns the round-trip time
+    pub fn ping(&mut self) -> Result<Duration> {
+        let sent_at = Instant::now();
+        let payload = self.next_request_id; // Any value works, the server sends it back
+        calls::ping(payload).send(|message| self.request(message))?;
+        Ok(sent_at.elapsed())
+    }
+
+    // Sends an EchoMessage and returns the content the server echoed
+    pub fn echo(&mut self, content: impl Into<String>) -> Result<String> {
+        calls::echo(content.into()).send(|message| self.request(message))
+    }
+
+    // Adds two numbers on the server, failing with an ARITHMETIC_OVERFLOW error if the
+    // sum doesn't fit in an i32
+    pub fn add(&mut self, a: i32, b: i32) -> Result<i32> {
+        self.add_with_mode(a, b, OverflowMode::Checked)
+    }
+
+    // Adds two numbers on the server, handling overflow as the mode says
+    pub fn add_with_mode(&mut self, a: i32, b: i32, mode: OverflowMode) -> Result<i32> {
+        calls::add(a, b, mode).send(|message| self.request(message))
+    }
+
+    // Subscribes to a topic and returns every topic the connection is subscribed to.
+    // Events are then delivered by `receive`.
+    pub fn subscribe(&mut self, topic: impl Into<String>) -> Result<Vec<String>> {
+        self.update_subscriptions(calls::subscribe(topic.into()))
+    }
+
+    // Unsubscribes from a topic and returns the topics the connection is left subscribed to
+    pub fn unsubscribe(&mut self, topic: impl Into<String>) -> Result<Vec<String>> {
+        self.update_subscriptions(calls::unsubscribe(topic.into()))
+    }
+
+    // Sends a Subscribe or Unsubscribe, remembering the resulting topics to restore them
+    // after reconnecting
+    fn update_subscriptions(&mut self, call: Call<Vec<String>>) -> Result<Vec<String>> {
+        let topics = call.send(|message| self.request(message))?;
+        self.subscriptions.clone_from(&topics);
+        Ok(topics)
+    }
+
+    // Publishes an event and returns how many subscribers it was delivered to
+    pub fn publish(&mut self, topic: impl Into<String>, payload: impl Into<Vec<u8>>) -> Result<u32> {
+        calls::publish(topic.into(), payload.into()).send(|message| self.request(message))
+    }
+
+    // Authenticates the connection with the method the server is configured with. For
+    // `AuthMethod::Hmac` a challenge is requested and answered, so the secret is never sent.
+    // The credentials are kept to authenticate again after reconnecting.
+    pub fn authenticate(&mut self, method: &AuthMethod) -> Result<()> {
+        let credentials = match method {
+            AuthMethod::Token(token) => Some(auth_request::Credentials::Token(token.clone())),
+            AuthMethod::Hmac(secret) => match self.auth_request(None)? {
+                // A server that doesn't require authentication accepts the connection as is
+                response if response.authenticated => None,
+                response => Some(auth_request::Credentials::ChallengeResponse(auth::challenge_response(
+                    secret,
+                    &response.challenge,
+                ))),
+            },
+        };
+        if let Some(credentials) = credentials {
+            let response = self.auth_request(Some(credentials))?;
+            if !response.authenticated {
+                return Err(ClientError::UnexpectedResponse(Some(server_message::Message::AuthResponse(response))));
+            }
+        }
+        self.auth = Some(method.clone());
+        Ok(())
+    }
+
+    // Sends an AuthRequest and returns the AuthResponse to it
+    fn auth_request(&mut self, credentials: Option<auth_request::Credentials>) -> Result<AuthResponse> {
+        match self.request(client_message::Message::AuthRequest(AuthRequest { credentials }))? {
+            server_message::Message::AuthResponse(response) => Ok(response),
+            other => Err(ClientError::UnexpectedResponse(Some(other))),
+        }
+    }
+
+    // Sends a request and waits for the response to it, returning error responses as
+    // `ClientError::Server`. If the connection is lost on the way and the reconnect policy
+    // allows it, requests that are safe to repeat are sent again on a new connection.
+    pub fn request(&mut self, message: client_message::Message) -> Result<server_message::Message> {
+        let retry = self.config.reconnect.as_ref().is_some_and(|policy| policy.retry_idempotent);
+        if !retry || !is_idempotent(&message) {
+            return self.exchange(message);
+        }
+        match self.exchange(message.clone()) {
+            Err(ClientError::Disconnected | ClientError::Io(_)) if self.lost && !self.reconnecting => {
+                debug!("Connection lost, sending the request again");
+                self.exchange(message)
+            }
+            result => result,
+        }
+    }
+
+    // Sends a request once and waits for the response to it
+    fn exchange(&mut self, message: client_message::Message) -> Result<server_message::Message> {
+        let request_id = self.send_request(message)?;
+        match self.receive_response(request_id)?.message {
+            Some(server_message::Message::ErrorResponse(error)) => Err(ClientError::Server(error)),
+            Some(message) => Ok(message),
+            None => Err(ClientError::UnexpectedResponse(None)),
+        }
+    }
+
+    // Sends a message as is
+    pub fn send(&mut self, message: ClientMessage) -> Result<()> {
+        self.send_raw(&encode_frame(&message))?;
+        debug!("Sent message: {:?}", message);
+        Ok(())
+    }
+
+    // Sends a request tagged with a fresh request id and returns that id
+    pub fn send_request(&mut self, message: client_message::Message) -> Result<u64> {
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+
+        self.send(ClientMessage {
+            message: Some(message),
+            request_id,
+        })?;
+        Ok(request_id)
+    }
+
+    // Writes raw bytes to the server without any framing
+    pub fn send_raw(&mut self, bytes: &[u8]) -> Result<()> {
+        self.ensure_connected()?;
+        let connection = self.connection.as_mut().ok_or(ClientError::NotConnected)?;
+        let result = connection.writer.write_all(bytes).and_then(|()| connection.writer.flush());
+        result.map_err(|e| self.write_failed(e))
+    }
+
+    // Receives the response to the given request id, holding on to any messages for other
+    // requests so they can be collected later
+    pub fn receive_response(&mut self, request_id: u64) -> Result<ServerMessage> {
+        if let Some(index) = self.pending.iter().position(|m| m.request_id == request_id) {
+            return Ok(self.pending.remove(index).unwrap());
+        }
+
+        loop {
+            let message = self.read_message()?;
+            if message.request_id == request_id {
+                return Ok(message);
+            }
+            self.hold(message);
+        }
+    }
+
+    // Holds a message for a later receive, dropping the oldest held one if
+    // `MAX_HELD_MESSAGES` are already held, with a warning once per run of drops
+    fn hold(&mut self, message: ServerMessage) {
+        if self.pending.len() < MAX_HELD_MESSAGES {
+            self.dropping = false;
+        } else {
+            if !self.dropping {
+                warn!("Dropping messages: {} are already held for later receives", MAX_HELD_MESSAGES);
+            }
+            self.dropping = true;
+            self.pending.pop_front();
+        }
+        self.pending.push_back(message);
+    }
+
+    // Receives the next message from the server, starting with any held-over ones
+    pub fn receive(&mut self) -> Result<ServerMessage> {
+        match self.pending.pop_front() {
+            Some(message) => Ok(message),
+            None => self.read_message(),
+        }
+    }
+
+    // Reads the next message from the server
+    fn read_message(&mut self) -> Result<ServerMessage> {
+        loop {
+            let frame = match self.frames.next_frame() {
+                Ok(frame) => frame,
+                Err(e) => {
+                    let detail = match FrameTooLarge::from_io_error(&e) {
+                        Some(too_large) => too_large.to_string(),
+                        None => e.to_string(),
+                    };
+                    return Err(self.protocol_error(detail));
+                }
+            };
+            if let Some(frame) = frame {
+                return ServerMessage::decode(frame.as_slice())
+                    .map_err(|e| self.protocol_error(format!("Failed to decode ServerMessage: {}", e)));
+            }
+
+            self.ensure_connected()?;
+            let connection = self.connection.as_mut().ok_or(ClientError::NotConnected)?;
+            match connection.reader.read(&mut self.buffer) {
+                Ok(0) => {
+                    info!("Server at {} disconnected", self.endpoints.current());
+                    self.lose_connection(&ClientError::Disconnected);
+                    return Err(ClientError::Disconnected);
+                }
+                Ok(bytes_read) => self.frames.extend(&self.buffer[..bytes_read]),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(self.read_failed(e)),
+            }
+        }
+    }
+
+    // Converts an error reading from the server, dropping the connection unless the read
+    // timed out
+    fn read_failed(&mut self, error: io::Error) -> ClientError {
+        let error = ClientError::from(error);
+        if !matches!(error, ClientError::TimedOut) {
+            warn!("Connection to {} failed: {}", self.endpoints.current(), error);
+            self.lose_connection(&error);
+        }
+        error
+    }
+
+    // Converts an error writing to the server and drops the connection, even after a
+    // timeout: part of the message may have been sent, and the server would read the next
+    // one as the rest of it
+    fn write_failed(&mut self, error: io::Error) -> ClientError {
+        let error = ClientError::from(error);
+        warn!("Connection to {} failed: {}", self.endpoints.current(), error);
+        self.lose_connection(&error);
+        error
+    }
+
+    // Drops a connection that can't be brought back in sync
+    fn protocol_error(&mut self, detail: String) -> ClientError {
+        warn!("Invalid data from {}: {}", self.endpoints.current(), detail);
+        let error = ClientError::Protocol(detail);
+        self.lose_connection(&error);
+        error
+    }
+
+    // Drops a connection that failed, counting the failure against its endpoint
+    fn lose_connection(&mut self, error: &ClientError) {
+        if self.connection.take().is_some() {
+            self.endpoints.lost(error);
+        }
+        self.lost = true;
+    }
+}
+
+// Returns true for requests that may be sent again without changing their outcome
+fn is_idempotent(message: &client_message::Message) -> bool {
+    matches!(
+        message,
+        client_message::Message::EchoMessage(_)
+            | client_message::Message::AddRequest(_)
+            | client_message::Message::Ping(_)
+            | client_message::Message::Subscribe(_)
+            | client_message::Message::Unsubscribe(_)
+    )
+}
@@ -0,0 +1,380 @@
+use crate::auth::AuthMethod;
+use crate::rate_limit::RateLimit;
+use crate::reconnect::ReconnectPolicy;
+#[cfg(feature = "tls")]
+use crate::tls::{ClientTlsConfig, TlsConfig};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+#[cfg(unix)]
+use std::path::PathBuf;
+
+// Default upper bound on the size of a single client message
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+// Default number of worker threads, and so of connections served at once
+pub const DEFAULT_WORKER_THREADS: usize = 16;
+
+// Default number of accepted connections that may wait for a free worker
+pub const DEFAULT_ACCEPT_QUEUE_SIZE: usize = 16;
+
+// Default time a connection may stay silent before the server closes it
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+// Default time `Server::stop` gives in-flight requests to complete
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Default number of failed authentication attempts after which a connection is closed
+pub const DEFAULT_MAX_AUTH_FAILURES: u32 = 3;
+
+// Default number of topics a connection may be subscribed to at once
+pub const DEFAULT_MAX_SUBSCRIPTIONS: usize = 64;
+
+// Default length in bytes of the longest topic a client may subscribe or publish to
+pub const DEFAULT_MAX_TOPIC_LENGTH: usize = 256;
+
+// Default time a connection has to authenticate before the server closes it
+pub const DEFAULT_AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Default time a `Client` waits for a connection to be established
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Default time a `Client` gives a connection attempt before also trying the next address
+// of the host, the value RFC 8305 recommends
+pub const DEFAULT_CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+// What the server does with a new connection while every worker is busy and the accept
+// queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SaturationPolicy {
+    // Answer with a `ServerBusy` error and close the connection
+    #[default]
+    Reject,
+    // Stop accepting until a worker frees up, leaving new clients in the OS backlog
+    Wait,
+}
+
+// Settings used to create a `Server`. Start from `ServerConfig::default()` and chain the
+// setters for the values to change, then pass the result to `Server::with_config`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerConfig {
+    pub bind_address: IpAddr,            // Interface to listen on
+    pub port: u16,                       // Port to listen on, 0 lets the OS choose one
+    pub dual_stack: bool,                // Accept IPv4 clients on an IPv6 listener as well
+    pub max_clients: Option<usize>,      // Connections served at once, `None` for no limit
+    pub read_timeout: Option<Duration>,  // Longest a client may take to finish sending a message
+    pub write_timeout: Option<Duration>, // Longest a write may block before the client is dropped
+    pub read_buffer_size: usize,         // Size of the per-connection read buffer
+    pub idle_timeout: Option<Duration>,  // Close connections that send nothing for this long
+    pub max_message_size: usize,         // Largest message accepted from a client
+    pub worker_threads: usize,           // Threads serving connections, one connection each
+    pub accept_queue_size: usize,        // Connections waiting for a free worker
+    pub saturation_policy: SaturationPolicy, // Handling of connections that find the queue full
+    pub shutdown_timeout: Duration,      // Longest `stop` waits for in-flight requests
+    pub auth: Option<AuthMethod>,        // Credentials clients must present, `None` to serve anyone
+    pub max_auth_failures: u32,          // Failed authentication attempts before the client is dropped
+    pub auth_timeout: Option<Duration>,  // Longest a client may take to authenticate, `None` for no limit
+    pub rate_limit: Option<RateLimit>,   // Requests each connection may send, `None` for no limit
+    pub peer_rate_limit: Option<RateLimit>, // Requests all connections from one IP address may send
+    pub max_subscriptions: usize,        // Topics a connection may be subscribed to at once
+    pub max_topic_length: usize,         // Longest topic in bytes a client may subscribe or publish to
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsConfig>,          // Serve connections over TLS instead of plain TCP
+    #[cfg(unix)]
+    pub unix_socket: Option<PathBuf>,    // Listen on this Unix domain socket instead of a TCP port
+    #[cfg(unix)]
+    pub unix_socket_mode: Option<u32>,   // Permission bits of the socket file, `None` to follow the umask
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 0,
+            dual_stack: false,
+            max_clients: None,
+            read_timeout: Some(Duration::from_secs(10)),
+            write_timeout: Some(Duration::from_secs(10)),
+            read_buffer_size: 1024,
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            worker_threads: DEFAULT_WORKER_THREADS,
+            accept_queue_size: DEFAULT_ACCEPT_QUEUE_SIZE,
+            saturation_policy: SaturationPolicy::Reject,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            auth: None,
+            max_auth_failures: DEFAULT_MAX_AUTH_FAILURES,
+            auth_timeout: Some(DEFAULT_AUTH_TIMEOUT),
+            rate_limit: None,
+            peer_rate_limit: None,
+            max_subscriptions: DEFAULT_MAX_SUBSCRIPTIONS,
+            max_topic_length: DEFAULT_MAX_TOPIC_LENGTH,
+            #[cfg(feature = "tls")]
+            tls: None,
+            #[cfg(unix)]
+            unix_socket: None,
+            #[cfg(unix)]
+            unix_socket_mode: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    // Listens on every IPv6 interface and, through dual-stack sockets, every IPv4 one
+    pub fn any_address(self) -> Self {
+        self.bind_address(IpAddr::V6(Ipv6Addr::UNSPECIFIED)).dual_stack(true)
+    }
+
+    // Sets the interface to listen on
+    pub fn bind_address(mut self, bind_address: IpAddr) -> Self {
+        self.bind_address = bind_address;
+        self
+    }
+
+    // Sets the port to listen on
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    // Makes an IPv6 listener accept IPv4 clients too; ignored for IPv4 bind addresses
+    pub fn dual_stack(mut self, dual_stack: bool) -> Self {
+        self.dual_stack = dual_stack;
+        self
+    }
+
+    // Limits how many clients are served at once; clients beyond the limit are turned away
+    // with a `ServerBusy` error
+    pub fn max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = Some(max_clients);
+        self
+    }
+
+    // Sets how long a client may take to send the rest of a message once its first bytes
+    // have arrived, `None` to wait forever
+    pub fn read_timeout(mut self, read_timeout: Option<Duration>) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    // Sets how long a write may block before the client is dropped, `None` to wait forever
+    pub fn write_timeout(mut self, write_timeout: Option<Duration>) -> Self {
+        self.write_timeout = write_timeout;
+        self
+    }
+
+    // Sets the size of the buffer each connection reads into
+    pub fn read_buffer_size(mut self, read_buffer_size: usize) -> Self {
+        self.read_buffer_size = read_buffer_size;
+        self
+    }
+
+    // Closes connections that send nothing, not even a `Ping`, for the given duration;
+    // `None` keeps silent connections open forever
+    pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    // Sets the largest message a client may send; larger messages are answered with a
+    // `MessageTooLarge` error and the connection is closed
+    pub fn max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    // Sets how many worker threads serve connections. Each worker serves one connection
+    // at a time, so this bounds both the thread count and the connections served at once.
+    pub fn worker_threads(mut self, worker_threads: usize) -> Self {
+        self.worker_threads = worker_threads;
+        self
+    }
+
+    // Sets how many accepted connections may wait for a free worker
+    pub fn accept_queue_size(mut self, accept_queue_size: usize) -> Self {
+        self.accept_queue_size = accept_queue_size;
+        self
+    }
+
+    // Sets what happens to new connections while the workers and the accept queue are full
+    pub fn saturation_policy(mut self, saturation_policy: SaturationPolicy) -> Self {
+        self.saturation_policy = saturation_policy;
+        self
+    }
+
+    // Sets how long a graceful stop waits for in-flight requests to be answered before the
+    // remaining connections are closed forcibly
+    pub fn shutdown_timeout(mut self, shutdown_timeout: Duration) -> Self {
+        self.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    // Requires clients to authenticate with an AuthRequest before any other request but
+    // Ping is served
+    pub fn auth(mut self, auth: AuthMethod) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    // Sets how many failed authentication attempts a connection may make before it is
+    // closed; 0 counts as 1. Requests other than Ping sent before authenticating count as
+    // failed attempts too.
+    pub fn max_auth_failures(mut self, max_auth_failures: u32) -> Self {
+        self.max_auth_failures = max_auth_failures;
+        self
+    }
+
+    // Sets how long a connection has to authenticate before it is closed, however many
+    // pings keep it from going idle meanwhile. `None` allows any time.
+    pub fn auth_timeout(mut self, auth_timeout: Option<Duration>) -> Self {
+        self.auth_timeout = auth_timeout;
+        self
+    }
+
+    // Limits the requests each connection may send. Requests beyond the limit are answered
+    // with a `RateLimited` error telling the client how long to wait.
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
+    // Limits the requests all connections from one IP address may send together, so a
+    // client can't get around `rate_limit` by opening more connections
+    pub fn peer_rate_limit(mut self, peer_rate_limit: RateLimit) -> Self {
+        self.peer_rate_limit = Some(peer_rate_limit);
+        self
+    }
+
+    // Sets how many topics a connection may be subscribed to at once. Subscriptions beyond
+    // it are answered with a `TooManySubscriptions` error.
+    pub fn max_subscriptions(mut self, max_subscriptions: usize) -> Self {
+        self.max_subscriptions = max_subscriptions;
+        self
+    }
+
+    // Sets the length in bytes of the longest topic a client may subscribe, unsubscribe or
+    // publish to. Longer topics are answered with a `MalformedRequest` error.
+    pub fn max_topic_length(mut self, max_topic_length: usize) -> Self {
+        self.max_topic_length = max_topic_length;
+        self
+    }
+
+    // Fails with `InvalidInput` for rate limits that would never let a request through
+    pub(crate) fn validate_rate_limits(&self) -> io::Result<()> {
+        self.rate_limit.iter().chain(&self.peer_rate_limit).try_for_each(RateLimit::validate)
+    }
+
+    // Serves connections over TLS with the given certificate, optionally requiring client
+    // certificates. Only supported by `Server`.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    // Listens on a Unix domain socket at the given path instead of a TCP port, so the
+    // server is only reachable from the same machine. A socket file left behind by a
+    // server that didn't exit cleanly is replaced. Only supported by `Server`.
+    #[cfg(unix)]
+    pub fn unix_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.unix_socket = Some(path.into());
+        self
+    }
+
+    // Sets the permission bits of the Unix domain socket file, such as 0o660 to only let
+    // the owner and its group connect
+    #[cfg(unix)]
+    pub fn unix_socket_mode(mut self, mode: u32) -> Self {
+        self.unix_socket_mode = Some(mode);
+        self
+    }
+}
+
+// Settings used to create a `Client`, built the same way as `ServerConfig`
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub connect_timeout: Duration,          // Longest a connection attempt to one address may take
+    pub connection_attempt_delay: Duration, // Wait before racing the next address of a host
+    pub read_timeout: Option<Duration>,     // Longest a receive waits for data, `None` to wait forever
+    pub write_timeout: Option<Duration>,    // Longest a send may block, `None` to wait forever
+    pub max_message_size: usize,            // Largest message accepted from the server
+    pub reconnect: Option<ReconnectPolicy>, // Re-establish lost connections, if set
+    #[cfg(feature = "tls")]
+    pub tls: Option<ClientTlsConfig>,       // Connect over TLS instead of plain TCP
+    #[cfg(feature = "tls")]
+    pub server_name: Option<String>,        // Name the server certificate must match, if not the host
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            connection_attempt_delay: DEFAULT_CONNECTION_ATTEMPT_DELAY,
+            read_timeout: Some(Duration::from_secs(10)),
+            write_timeout: Some(Duration::from_secs(10)),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            reconnect: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            #[cfg(feature = "tls")]
+            server_name: None,
+        }
+    }
+}
+
+impl ClientConfig {
+    // Sets how long a connection attempt to one address may take
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    // Sets how long a connection attempt to one address of a host gets before the next
+    // address is tried alongside it
+    pub fn connection_attempt_delay(mut self, connection_attempt_delay: Duration) -> Self {
+        self.connection_attempt_delay = connection_attempt_delay;
+        self
+    }
+
+    // Sets how long a receive waits for data before failing, `None` to wait forever
+    pub fn read_timeout(mut self, read_timeout: Option<Duration>) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    // Sets how long a send may block before failing, `None` to wait forever
+    pub fn write_timeout(mut self, write_timeout: Option<Duration>) -> Self {
+        self.write_timeout = write_timeout;
+        self
+    }
+
+    // Sets the largest message accepted from the server; a larger one closes the connection
+    pub fn max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    // Re-establishes the connection as the policy says when it is lost, instead of
+    // failing every request until `connect` is called again
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Some(policy);
+        self
+    }
+
+    // Connects over TLS, trusting the roots of the given configuration
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: ClientTlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    // Sets the name the server certificate must be valid for, when it isn't the host
+    // connected to, such as when connecting by IP address
+    #[cfg(feature = "tls")]
+    pub fn server_name(mut self, server_name: impl Into<String>) -> Self {
+        self.server_name = Some(server_name.into());
+        self
+    }
+}
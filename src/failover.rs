@@ -0,0 +1,166 @@
+use crate::client::Endpoint;
+use log::debug;
+use std::{
+    fmt, io,
+    net::{SocketAddr, TcpStream},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+// Health of one endpoint of a `Client`, as of the last connection to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointHealth {
+    pub endpoint: Endpoint,
+    pub consecutive_failures: u32,          // Failed or lost connections since the last established one
+    pub last_connected: Option<Instant>,    // When a connection to the endpoint was last established
+    pub last_failure: Option<Instant>,      // When a connection to the endpoint last failed or was lost
+    pub last_error: Option<String>,         // What went wrong that time
+}
+
+impl EndpointHealth {
+    fn new(endpoint: Endpoint) -> Self {
+        EndpointHealth {
+            endpoint,
+            consecutive_failures: 0,
+            last_connected: None,
+            last_failure: None,
+            last_error: None,
+        }
+    }
+
+    // Returns true unless the last connection to the endpoint failed or was lost
+    pub fn is_healthy(&self) -> bool {
+        self.consecutive_failures == 0
+    }
+}
+
+// Endpoints a client may connect to, with their health and the one last connected to
+pub(crate) struct Endpoints {
+    health: Vec<EndpointHealth>,
+    current: usize,
+}
+
+impl Endpoints {
+    // Panics if there are no endpoints, as a client needs somewhere to connect to
+    pub fn new(endpoints: Vec<Endpoint>) -> Self {
+        assert!(!endpoints.is_empty(), "A client needs at least one endpoint");
+        Endpoints {
+            health: endpoints.into_iter().map(EndpointHealth::new).collect(),
+            current: 0,
+        }
+    }
+
+    // Endpoint connected to, or last connected to
+    pub fn current(&self) -> &Endpoint {
+        &self.health[self.current].endpoint
+    }
+
+    pub fn get(&self, index: usize) -> &Endpoint {
+        &self.health[index].endpoint
+    }
+
+    pub fn health(&self) -> &[EndpointHealth] {
+        &self.health
+    }
+
+    // Indices of the endpoints in the order to try them: those with the fewest failures in
+    // a row first, in the order they were given among equals. An endpoint whose connection
+    // was lost so goes behind the others, and the client fails over to them.
+    pub fn connection_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.health.len()).collect();
+        order.sort_by_key(|&index| self.health[index].consecutive_failures);
+        order
+    }
+
+    // Records a connection established to an endpoint, making it the current one
+    pub fn connected(&mut self, index: usize) {
+        let health = &mut self.health[index];
+        health.consecutive_failures = 0;
+        health.last_connected = Some(Instant::now());
+        self.current = index;
+    }
+
+    // Records a failed connection attempt to an endpoint
+    pub fn failed(&mut self, index: usize, error: &dyn fmt::Display) {
+        let health = &mut self.health[index];
+        health.consecutive_failures = health.consecutive_failures.saturating_add(1);
+        health.last_failure = Some(Instant::now());
+        health.last_error = Some(error.to_string());
+    }
+
+    // Records the loss of the connection to the current endpoint
+    pub fn lost(&mut self, error: &dyn fmt::Display) {
+        self.failed(self.current, error);
+    }
+}
+
+// Connects to the first of the addresses that accepts, racing them as described by Happy
+// Eyeballs (RFC 8305): each attempt gets `attempt_delay` before the next address is tried
+// alongside it, and a failed attempt starts the next one at once. Attempts that lose the
+// race are dropped once they finish.
+pub(crate) fn connect_tcp(
+    addresses: &[SocketAddr],
+    timeout: Duration,
+    attempt_delay: Duration,
+) -> io::Result<TcpStream> {
+    if let [address] = addresses {
+        return TcpStream::connect_timeout(address, timeout);
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let mut remaining = interleave_families(addresses).into_iter();
+    let mut in_flight = 0;
+    let mut last_error = None;
+    let mut start_next = true;
+    loop {
+        if start_next {
+            if let Some(address) = remaining.next() {
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    // The receiver is gone once another attempt has won
+                    let _ = sender.send((address, TcpStream::connect_timeout(&address, timeout)));
+                });
+                in_flight += 1;
+            }
+        }
+        if in_flight == 0 {
+            return Err(last_error
+                .unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Host resolved to no address")));
+        }
+
+        // Wait for an attempt to finish, giving up on waiting once it is time for the next
+        let result = if remaining.len() > 0 {
+            receiver.recv_timeout(attempt_delay).ok()
+        } else {
+            receiver.recv().ok()
+        };
+        start_next = match result {
+            Some((_, Ok(stream))) => return Ok(stream),
+            Some((address, Err(e))) => {
+                debug!("Could not connect to {}: {}", address, e);
+                in_flight -= 1;
+                last_error = Some(e);
+                true
+            }
+            None => true,
+        };
+    }
+}
+
+// Orders addresses alternating between IPv6 and IPv4, starting with the family the
+// resolver listed first, so a family that doesn't work costs one attempt delay at most
+fn interleave_families(addresses: &[SocketAddr]) -> Vec<SocketAddr> {
+    let first_is_ipv6 = addresses.first().is_some_and(SocketAddr::is_ipv6);
+    let (preferred, other): (Vec<SocketAddr>, Vec<SocketAddr>) =
+        addresses.iter().partition(|address| address.is_ipv6() == first_is_ipv6);
+
+    let mut interleaved = Vec::with_capacity(addresses.len());
+    let (mut preferred, mut other) = (preferred.into_iter(), other.into_iter());
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => return interleaved,
+            (first, second) => interleaved.extend(first.into_iter().chain(second)),
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use prost::Message;
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Write},
+};
+
+// Largest number of bytes a varint-encoded u64 length prefix can occupy
+const MAX_VARINT_LEN: usize = 10;
+
+// Error raised when a frame announces a payload larger than the configured maximum
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameTooLarge {
+    pub length: usize,   // Payload length announced by the frame header
+    pub max_size: usize, // Largest payload length the buffer accepts
+}
+
+impl FrameTooLarge {
+    // Returns the oversized frame details if `error` was caused by one
+    pub fn from_io_error(error: &io::Error) -> Option<&FrameTooLarge> {
+        error.get_ref().and_then(|inner| inner.downcast_ref::<FrameTooLarge>())
+    }
+}
+
+impl fmt::Display for FrameTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Message of {} bytes exceeds the maximum of {} bytes",
+            self.length, self.max_size
+        )
+    }
+}
+
+impl Error for FrameTooLarge {}
+
+// Encodes a message as a length-delimited frame: a varint length prefix followed by the
+// message bytes. Compatible with prost's `encode_length_delimited`/`decode_length_delimited`.
+pub fn encode_frame<M: Message>(message: &M) -> Vec<u8> {
+    message.encode_length_delimited_to_vec()
+}
+
+// Encodes a message as a length-delimited frame and writes it to the given writer
+pub fn write_frame<W: Write, M: Message>(writer: &mut W, message: &M) -> io::Result<()> {
+    writer.write_all(&encode_frame(message))?;
+    writer.flush()
+}
+
+// Reassembles length-delimited frames from a byte stream that may deliver them split
+// across several reads or coalesced into a single read
+#[derive(Debug)]
+pub struct FrameBuffer {
+    buffer: Vec<u8>,       // Bytes received but not yet consumed as a complete frame
+    max_frame_size: usize, // Largest payload accepted before the stream is rejected
+}
+
+impl FrameBuffer {
+    // Creates an empty frame buffer that accepts frames of any size
+    pub fn new() -> Self {
+        Self::with_max_frame_size(usize::MAX)
+    }
+
+    // Creates an empty frame buffer that rejects frames larger than `max_frame_size`
+    pub fn with_max_frame_size(max_frame_size: usize) -> Self {
+        FrameBuffer {
+            buffer: Vec::new(),
+            max_frame_size,
+        }
+    }
+
+    // Appends bytes read from the stream to the reassembly buffer
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    // Number of buffered bytes that have not been returned as a frame yet
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    // Returns true if no partial frame is buffered
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    // Removes and returns the payload of the next complete frame, or `None` if more
+    // bytes are needed to complete it. Oversized frames are rejected with a
+    // `FrameTooLarge` error as soon as their header arrives, before the payload is buffered.
+    pub fn next_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let (length, prefix_len) = match decode_length_prefix(&self.buffer)? {
+            Some(prefix) => prefix,
+            None => return Ok(None),
+        };
+
+        if length > self.max_frame_size {
+            let too_large = FrameTooLarge {
+                length,
+                max_size: self.max_frame_size,
+            };
+            return Err(io::Error::new(io::ErrorKind::InvalidData, too_large));
+        }
+
+        let frame_end = prefix_len
+            .checked_add(length)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Frame length overflow"))?;
+        if self.buffer.len() < frame_end {
+            // The frame is split across reads: wait for the rest of it
+            return Ok(None);
+        }
+
+        let payload = self.buffer[prefix_len..frame_end].to_vec();
+        self.buffer.drain(..frame_end);
+        Ok(Some(payload))
+    }
+}
+
+impl Default for FrameBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Decodes the varint length prefix at the start of `buffer`, returning the frame length
+// and the number of bytes the prefix occupies, or `None` if the prefix is incomplete
+fn decode_length_prefix(buffer: &[u8]) -> io::Result<Option<(usize, usize)>> {
+    let mut length: u64 = 0;
+    for (index, byte) in buffer.iter().take(MAX_VARINT_LEN).enumerate() {
+        length |= u64::from(byte & 0x7f) << (7 * index);
+        if byte & 0x80 == 0 {
+            let length = usize::try_from(length).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "Frame length does not fit in memory")
+            })?;
+            return Ok(Some((length, index + 1)));
+        }
+    }
+
+    if buffer.len() >= MAX_VARINT_LEN {
+        // A varint never needs more than ten bytes: the stream is corrupt
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid frame length prefix"));
+    }
+    Ok(None)
+}
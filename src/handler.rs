@@ -0,0 +1,130 @@
+use crate::message::{
+    client_message, server_message, AddRequest, AddResponse, EchoMessage, ErrorCode, ErrorResponse,
+    OverflowMode,
+};
+use log::warn;
+use std::{fmt, net::SocketAddr};
+#[cfg(unix)]
+use std::path::PathBuf;
+
+// Identifies a connection among those accepted by a server
+pub type ConnectionId = u64;
+
+// Address of one end of a connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    Tcp(SocketAddr),
+    // Path of a Unix domain socket, `None` for the unnamed sockets clients usually connect from
+    #[cfg(unix)]
+    Unix(Option<PathBuf>),
+}
+
+impl From<SocketAddr> for Address {
+    fn from(address: SocketAddr) -> Self {
+        Address::Tcp(address)
+    }
+}
+
+impl PartialEq<SocketAddr> for Address {
+    fn eq(&self, other: &SocketAddr) -> bool {
+        matches!(self, Address::Tcp(address) if address == other)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Tcp(address) => address.fmt(f),
+            #[cfg(unix)]
+            Address::Unix(Some(path)) => write!(f, "unix:{}", path.display()),
+            #[cfg(unix)]
+            Address::Unix(None) => f.write_str("unix:(unnamed)"),
+        }
+    }
+}
+
+// Details about the connection a request arrived on
+#[derive(Debug, Clone)]
+pub struct ConnectionContext {
+    pub connection_id: ConnectionId, // Unique among the server's connections
+    pub peer_addr: Address,     // Address of the connected client
+    pub local_addr: Address,    // Address the server accepted the connection on
+}
+
+// Handles requests sent by clients. The server offers each request to its handlers in turn
+// and replies with the first response returned; a handler returns `None` for requests it
+// doesn't deal with. Application messages travel in the `application_message` arm as a
+// `prost_types::Any`, so new request types can be handled without changing the server.
+pub trait Handler: Send + Sync {
+    fn handle(
+        &self,
+        request: &client_message::Message,
+        context: &ConnectionContext,
+    ) -> Option<server_message::Message>;
+}
+
+// Responds to an EchoMessage with the same content
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EchoHandler;
+
+impl Handler for EchoHandler {
+    fn handle(
+        &self,
+        request: &client_message::Message,
+        _context: &ConnectionContext,
+    ) -> Option<server_message::Message> {
+        match request {
+            client_message::Message::EchoMessage(echo) => {
+                Some(server_message::Message::EchoMessage(EchoMessage {
+                    content: echo.content.clone(),
+                }))
+            }
+            _ => None,
+        }
+    }
+}
+
+// Responds to an AddRequest with the sum of `a` and `b`, or an error if it overflows
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AddHandler;
+
+impl Handler for AddHandler {
+    fn handle(
+        &self,
+        request: &client_message::Message,
+        _context: &ConnectionContext,
+    ) -> Option<server_message::Message> {
+        let add = match request {
+            client_message::Message::AddRequest(add) => add,
+            _ => return None,
+        };
+
+        Some(match add_with_mode(add) {
+            Some(result) => server_message::Message::AddResponse(AddResponse { result }),
+            None => {
+                warn!("AddRequest overflowed: {} + {}", add.a, add.b);
+                let detail = format!("{} + {} overflows a 32-bit integer", add.a, add.b);
+                error_response(ErrorCode::ArithmeticOverflow, detail)
+            }
+        })
+    }
+}
+
+// Adds the operands of an AddRequest using its overflow mode, returning `None` when a
+// checked addition overflows
+fn add_with_mode(add: &AddRequest) -> Option<i32> {
+    match add.mode() {
+        OverflowMode::Checked => add.a.checked_add(add.b),
+        OverflowMode::Saturating => Some(add.a.saturating_add(add.b)),
+        OverflowMode::Wrapping => Some(add.a.wrapping_add(add.b)),
+    }
+}
+
+// Builds an error response payload
+pub fn error_response(code: ErrorCode, detail: String) -> server_message::Message {
+    server_message::Message::ErrorResponse(ErrorResponse {
+        code: code.into(),
+        detail,
+        ..Default::default()
+    })
+}
@@ -1,4 +1,23 @@
+#[cfg(feature = "async")]
+pub mod async_server;
+pub mod auth;
+mod calls;
+pub mod client;
+pub mod config;
+pub mod failover;
+pub mod framing;
+pub mod handler;
+mod pool;
+pub mod rate_limit;
+pub mod reconnect;
+pub mod registry;
 pub mod server;
+mod session;
+pub mod shared_client;
+#[cfg(feature = "tls")]
+pub mod tls;
+mod topics;
+mod transport;
 
 pub mod message {
     include!(concat!(env!("OUT_DIR"), "/messages.rs"));
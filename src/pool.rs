@@ -0,0 +1,155 @@
+use log::error;
+use std::{
+    collections::VecDeque,
+    io,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+// Work executed by one of the pool's threads
+pub(crate) type Job = Box<dyn FnOnce() + Send + 'static>;
+
+// Queue state shared between the pool and its workers
+struct State {
+    queue: VecDeque<Job>, // Jobs not yet picked up by a worker
+    idle: usize,          // Workers free to pick up a job
+    closed: bool,         // Set once the pool stops taking jobs
+}
+
+struct Shared {
+    state: Mutex<State>,
+    job_available: Condvar,   // Signalled when a job is queued or the pool closes
+    space_available: Condvar, // Broadcast when a worker frees up or the pool closes
+    queue_size: usize,        // Jobs that may wait while every worker is busy
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // Jobs run outside the lock, so a poisoned lock still holds consistent state
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Returns true if a new job would have to wait beyond the queue's capacity
+    fn is_full(&self, state: &State) -> bool {
+        state.queue.len() >= state.idle + self.queue_size
+    }
+}
+
+// Fixed set of worker threads fed from a bounded queue of jobs
+pub(crate) struct ThreadPool {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>, // Threads running the jobs
+}
+
+impl ThreadPool {
+    // Starts `size` workers and a queue that holds up to `queue_size` jobs waiting for a
+    // free worker; with a queue size of 0 jobs are only accepted while a worker is idle
+    pub fn new(name: &str, size: usize, queue_size: usize) -> io::Result<Self> {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                idle: size, // Workers that haven't started yet will still pick up jobs
+                closed: false,
+            }),
+            job_available: Condvar::new(),
+            space_available: Condvar::new(),
+            queue_size,
+        });
+
+        let workers = (0..size)
+            .map(|index| {
+                let shared = Arc::clone(&shared);
+                thread::Builder::new()
+                    .name(format!("{}-{}", name, index))
+                    .spawn(move || run_worker(&shared))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(ThreadPool { shared, workers })
+    }
+
+    // Returns true if `try_execute` would hand a job back for lack of room
+    pub fn is_full(&self) -> bool {
+        let state = self.shared.lock();
+        self.shared.is_full(&state)
+    }
+
+    // Queues a job without blocking, handing it back if every worker is busy and the
+    // queue is full
+    pub fn try_execute(&self, job: Job) -> Result<(), Job> {
+        let mut state = self.shared.lock();
+        if state.closed || self.shared.is_full(&state) {
+            return Err(job);
+        }
+        state.queue.push_back(job);
+        self.shared.job_available.notify_one();
+        Ok(())
+    }
+
+    // Queues a job, waiting for room in the queue if every worker is busy
+    pub fn execute(&self, job: Job) -> Result<(), Job> {
+        let mut state = self.shared.lock();
+        while !state.closed && self.shared.is_full(&state) {
+            state = self.shared.space_available.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        if state.closed {
+            return Err(job);
+        }
+        state.queue.push_back(job);
+        self.shared.job_available.notify_one();
+        Ok(())
+    }
+
+    // Waits up to `timeout` for every queued job to be picked up and finished, returning
+    // false if jobs are still running when it expires
+    pub fn wait_idle(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        while !state.queue.is_empty() || state.idle < self.workers.len() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return false;
+            }
+            state = self.shared.space_available.wait_timeout(state, remaining).unwrap_or_else(|e| e.into_inner()).0;
+        }
+        true
+    }
+
+    // Lets the workers finish the queued jobs, then waits for them to exit
+    pub fn join(self) {
+        self.shared.lock().closed = true;
+        self.shared.job_available.notify_all();
+        self.shared.space_available.notify_all();
+
+        for worker in self.workers {
+            let _ = worker.join();
+        }
+    }
+}
+
+// Runs jobs from the queue until the pool is closed and the queue drained
+fn run_worker(shared: &Shared) {
+    let mut state = shared.lock();
+    loop {
+        if let Some(job) = state.queue.pop_front() {
+            state.idle -= 1;
+            drop(state); // Run the job without holding the lock
+
+            // A panicking job must not take its worker down with it and shrink the pool
+            if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                error!("Job panicked on worker thread");
+            }
+
+            state = shared.lock();
+            state.idle += 1;
+            // Both `execute` and `wait_idle` may be waiting
+            shared.space_available.notify_all();
+        } else if state.closed {
+            return;
+        } else {
+            state = shared.job_available.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
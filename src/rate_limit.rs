@@ -0,0 +1,196 @@
+use crate::config::ServerConfig;
+use crate::handler::Address;
+use crate::message::{server_message, ErrorCode, ErrorResponse};
+use std::{
+    collections::HashMap,
+    io,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// Peers tracked before buckets that have refilled are dropped
+const PRUNE_THRESHOLD: usize = 1024;
+
+// Shortest time between two passes dropping the buckets that have refilled
+const PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+// Most peers tracked at once. Reaching it drops the half of the buckets used least recently,
+// forgiving those peers, so a flood from many addresses can't grow the map without bound.
+const MAX_PEERS: usize = 16 * 1024;
+
+// Token-bucket limit on the requests a client may send. Each request takes a token;
+// tokens are added at `rate` per second, up to `burst` of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub rate: u32,  // Requests per second a client may keep sending
+    pub burst: u32, // Requests a client may send at once after being quiet
+}
+
+impl RateLimit {
+    // Allows `rate` requests per second, all of which may be sent at once
+    pub fn per_second(rate: u32) -> Self {
+        RateLimit { rate, burst: rate }
+    }
+
+    // Sets how many requests may be sent at once after being quiet
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = burst;
+        self
+    }
+
+    // Fails with `InvalidInput` for limits that would never let a request through
+    pub(crate) fn validate(&self) -> io::Result<()> {
+        if self.rate == 0 || self.burst == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Rate limit rate and burst must be non-zero"));
+        }
+        Ok(())
+    }
+}
+
+// Tokens available to one connection or peer
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,       // Tokens available as of `updated`
+    updated: Instant,  // When a token was last taken, or the bucket created
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Self {
+        TokenBucket {
+            limit,
+            tokens: limit.burst.into(),
+            updated: Instant::now(),
+        }
+    }
+
+    // Tokens available at the given time, counting those earned since the last update
+    fn tokens_at(&self, now: Instant) -> f64 {
+        let earned = now.saturating_duration_since(self.updated).as_secs_f64() * f64::from(self.limit.rate);
+        (self.tokens + earned).min(self.limit.burst.into())
+    }
+
+    // Takes a token, or returns how long until one is available
+    fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        self.tokens = self.tokens_at(now);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / f64::from(self.limit.rate)))
+        }
+    }
+
+    // Returns true if the bucket has refilled completely, leaving it as it is so `updated`
+    // still tells when it was last used
+    fn is_full(&self, now: Instant) -> bool {
+        self.tokens_at(now) >= self.limit.burst.into()
+    }
+}
+
+// Buckets shared by every connection from the same IP address
+pub(crate) struct PeerLimits {
+    limit: RateLimit,
+    peers: Mutex<Peers>,
+}
+
+// Buckets of the peers tracked, and when the ones that refilled were last dropped
+struct Peers {
+    buckets: HashMap<IpAddr, TokenBucket>,
+    pruned: Instant,
+}
+
+impl Peers {
+    // Drops the buckets that have refilled, at most once per `PRUNE_INTERVAL` as it goes
+    // through all of them
+    fn prune(&mut self, now: Instant) {
+        if self.buckets.len() >= PRUNE_THRESHOLD && now.saturating_duration_since(self.pruned) >= PRUNE_INTERVAL {
+            // A full bucket is the same as no bucket
+            self.buckets.retain(|_, bucket| !bucket.is_full(now));
+            self.pruned = now;
+        }
+    }
+
+    // Makes room for a new peer once `MAX_PEERS` are tracked by dropping the half of the
+    // buckets used least recently, which keeps the cost per new peer constant on average
+    fn make_room(&mut self) {
+        if self.buckets.len() < MAX_PEERS {
+            return;
+        }
+        let mut by_use: Vec<(Instant, IpAddr)> = self.buckets.iter().map(|(&ip, bucket)| (bucket.updated, ip)).collect();
+        let half = by_use.len() / 2;
+        by_use.select_nth_unstable(half);
+        for (_, ip) in &by_use[..half] {
+            self.buckets.remove(ip);
+        }
+    }
+}
+
+impl PeerLimits {
+    // Creates the shared buckets if the configuration limits requests per peer
+    pub fn from_config(config: &ServerConfig) -> Option<Arc<Self>> {
+        config.peer_rate_limit.map(|limit| {
+            Arc::new(PeerLimits {
+                limit,
+                peers: Mutex::new(Peers {
+                    buckets: HashMap::new(),
+                    pruned: Instant::now(),
+                }),
+            })
+        })
+    }
+
+    fn take(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        // Buckets are updated whole, so a poisoned lock still holds consistent state
+        let mut peers = self.peers.lock().unwrap_or_else(|e| e.into_inner());
+        peers.prune(now);
+        if !peers.buckets.contains_key(&ip) {
+            peers.make_room();
+        }
+        peers.buckets.entry(ip).or_insert_with(|| TokenBucket::new(self.limit)).take(now)
+    }
+}
+
+// Limits applied to the requests of one connection
+pub(crate) struct RateLimiter {
+    connection: Option<TokenBucket>,             // Tokens of the connection itself
+    peer: Option<(Arc<PeerLimits>, IpAddr)>,     // Tokens shared with other connections from its IP
+}
+
+impl RateLimiter {
+    // Creates the limiter of a new connection. Unix domain socket peers have no IP address,
+    // so only the per-connection limit applies to them.
+    pub fn new(config: &ServerConfig, peer_limits: Option<&Arc<PeerLimits>>, peer_addr: &Address) -> Self {
+        let peer = match (peer_limits, peer_addr) {
+            (Some(peer_limits), Address::Tcp(address)) => Some((Arc::clone(peer_limits), address.ip())),
+            _ => None,
+        };
+        RateLimiter {
+            connection: config.rate_limit.map(TokenBucket::new),
+            peer,
+        }
+    }
+
+    // Takes a token for a request, or returns how long the client should wait before
+    // sending it again
+    pub fn check(&mut self) -> Result<(), Duration> {
+        let now = Instant::now();
+        if let Some(ref mut bucket) = self.connection {
+            bucket.take(now)?;
+        }
+        if let Some((ref peer_limits, ip)) = self.peer {
+            peer_limits.take(ip, now)?;
+        }
+        Ok(())
+    }
+}
+
+// Builds the error answering a request refused by a rate limit
+pub(crate) fn rate_limited(retry_after: Duration) -> server_message::Message {
+    server_message::Message::ErrorResponse(ErrorResponse {
+        code: ErrorCode::RateLimited.into(),
+        detail: "Too many requests".to_string(),
+        retry_after_ms: u32::try_from(retry_after.as_nanos().div_ceil(1_000_000)).unwrap_or(u32::MAX),
+    })
+}
@@ -0,0 +1,131 @@
+use crate::client::Endpoint;
+use std::{fmt, sync::Arc, time::Duration};
+
+// Callback run once a lost connection has been re-established, with the endpoint and the
+// number of connection attempts it took
+type ReconnectCallback = Arc<dyn Fn(&Endpoint, u32) + Send + Sync>;
+
+// How a `Client` re-establishes a connection it lost, set with `ClientConfig::reconnect`.
+// The next send or receive after the connection is lost reconnects, waiting between failed
+// attempts for a delay that grows by `multiplier` each time, up to `max_delay`. A random
+// part of each delay, up to `jitter` of it, is skipped so clients cut off together don't
+// all come back at once. Authentication and subscriptions are restored on the new
+// connection.
+#[derive(Clone)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,       // Wait after the first failed attempt
+    pub max_delay: Duration,           // Longest wait between attempts
+    pub multiplier: f64,               // Growth of the wait after each failed attempt
+    pub jitter: f64,                   // Fraction of each wait that may be skipped, from 0 to 1
+    pub max_attempts: Option<u32>,     // Attempts before giving up, `None` to never give up
+    pub retry_idempotent: bool,        // Resend requests that are safe to repeat after reconnecting
+    on_reconnect: Option<ReconnectCallback>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: 0.5,
+            max_attempts: Some(10),
+            retry_idempotent: false,
+            on_reconnect: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    // Sets the wait after the first failed attempt
+    pub fn initial_delay(mut self, initial_delay: Duration) -> Self {
+        self.initial_delay = initial_delay;
+        self
+    }
+
+    // Sets the longest wait between attempts
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    // Sets how much the wait grows after each failed attempt; values below 1 are treated as 1
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    // Sets the fraction of each wait that may be skipped, clamped to the range 0 to 1. NaN
+    // means no jitter.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    // Sets how many attempts are made before giving up, `None` to never give up
+    pub fn max_attempts(mut self, max_attempts: Option<u32>) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    // Sets whether requests that are safe to repeat, such as echo, add and ping, are sent
+    // again on the new connection when the connection is lost while awaiting their response
+    pub fn retry_idempotent(mut self, retry_idempotent: bool) -> Self {
+        self.retry_idempotent = retry_idempotent;
+        self
+    }
+
+    // Sets a callback run once a lost connection has been re-established, with the number
+    // of attempts it took
+    pub fn on_reconnect(mut self, callback: impl Fn(&Endpoint, u32) + Send + Sync + 'static) -> Self {
+        self.on_reconnect = Some(Arc::new(callback));
+        self
+    }
+
+    // Returns true if another attempt may follow the given number of failed ones
+    pub(crate) fn may_retry(&self, attempts: u32) -> bool {
+        self.max_attempts.is_none_or(|max_attempts| attempts < max_attempts)
+    }
+
+    // Wait before the attempt following the given number of failed ones
+    pub fn delay(&self, attempts: u32) -> Duration {
+        let exponent = i32::try_from(attempts.saturating_sub(1)).unwrap_or(i32::MAX);
+        let growth = self.multiplier.max(1.0).powi(exponent);
+        let delay = Duration::try_from_secs_f64(self.initial_delay.as_secs_f64() * growth)
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay));
+        // `Duration::mul_f64` panics on NaN, which clamping lets through
+        let jitter = if self.jitter.is_nan() { 0.0 } else { self.jitter.clamp(0.0, 1.0) };
+        delay.mul_f64(1.0 - jitter * random_fraction())
+    }
+
+    pub(crate) fn notify(&self, endpoint: &Endpoint, attempts: u32) {
+        if let Some(ref callback) = self.on_reconnect {
+            callback(endpoint, attempts);
+        }
+    }
+}
+
+impl fmt::Debug for ReconnectPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReconnectPolicy")
+            .field("initial_delay", &self.initial_delay)
+            .field("max_delay", &self.max_delay)
+            .field("multiplier", &self.multiplier)
+            .field("jitter", &self.jitter)
+            .field("max_attempts", &self.max_attempts)
+            .field("retry_idempotent", &self.retry_idempotent)
+            .field("on_reconnect", &self.on_reconnect.is_some())
+            .finish()
+    }
+}
+
+// Random number from 0 up to 1, or 0 if the system has no randomness to offer, which only
+// costs the jitter
+fn random_fraction() -> f64 {
+    let mut bytes = [0; 8];
+    match getrandom::getrandom(&mut bytes) {
+        // The top 53 bits fill the mantissa exactly
+        Ok(()) => (u64::from_le_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64,
+        Err(_) => 0.0,
+    }
+}
@@ -0,0 +1,230 @@
+use crate::handler::{Address, ConnectionId};
+use crate::message::{server_message, ServerMessage};
+use crate::transport::{Stream, Writer};
+use log::warn;
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{self, Write},
+    net::Shutdown,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime},
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Snapshot of a connection being served, as returned by `Server::connections`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    pub id: ConnectionId,          // Id also passed to handlers in `ConnectionContext`
+    pub peer_addr: Address,        // Address of the connected client
+    pub connected_at: SystemTime,  // When a worker started serving the connection
+    pub last_activity: SystemTime, // When the client last sent data
+    pub bytes_received: u64,       // Bytes read from the client
+    pub bytes_sent: u64,           // Bytes written to the client, pushed messages included
+    pub requests: u64,             // Requests answered, including those answered with an error
+    pub failed_requests: u64,      // Requests answered with an error
+    pub subscriptions: Vec<String>, // Topics the client is subscribed to, in order
+}
+
+// Activity counters of a connection, updated by its worker and read by the registry
+pub(crate) struct ConnectionStats {
+    started: Instant,            // Monotonic time the connection was registered
+    connected_at: SystemTime,    // Wall-clock time the connection was registered
+    last_activity: AtomicU64,    // Microseconds from `started` to the last data received
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    requests: AtomicU64,
+    failed_requests: AtomicU64,
+}
+
+impl ConnectionStats {
+    fn new() -> Self {
+        ConnectionStats {
+            started: Instant::now(),
+            connected_at: SystemTime::now(),
+            last_activity: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            requests: AtomicU64::new(0),
+            failed_requests: AtomicU64::new(0),
+        }
+    }
+
+    // Records data read from the client
+    pub fn record_received(&self, bytes: usize) {
+        let since_start = u64::try_from(self.started.elapsed().as_micros()).unwrap_or(u64::MAX);
+        self.last_activity.store(since_start, Ordering::Relaxed);
+        self.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    // Records responses sent to the client, each answering one request
+    pub fn record_responses(&self, responses: &[ServerMessage]) {
+        let failed = responses
+            .iter()
+            .filter(|response| matches!(response.message, Some(server_message::Message::ErrorResponse(_))))
+            .count();
+        self.requests.fetch_add(responses.len() as u64, Ordering::Relaxed);
+        self.failed_requests.fetch_add(failed as u64, Ordering::Relaxed);
+    }
+}
+
+// A registered connection. Everything written to the client goes through `write`, so
+// responses from the connection's worker and messages pushed by other threads never
+// interleave within a frame.
+pub(crate) struct Connection {
+    stream: Stream,          // Handle used to shut the connection down
+    writer: Mutex<Writer>,   // Half of the connection written to, one batch of frames at a time
+    peer_addr: Address,      // Address of the connected client
+    stats: ConnectionStats,  // Counters updated as the client is served
+    subscriptions: Mutex<BTreeSet<String>>, // Topics the client receives events for
+}
+
+impl Connection {
+    // Writes encoded frames to the client. A failed write may leave a partial frame
+    // behind, so the connection is shut down rather than left out of sync.
+    pub fn write(&self, frames: &[u8]) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = writer.write_all(frames) {
+            let _ = self.stream.shutdown(Shutdown::Both);
+            return Err(e);
+        }
+        self.stats.bytes_sent.fetch_add(frames.len() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Counters updated as the client is served
+    pub fn stats(&self) -> &ConnectionStats {
+        &self.stats
+    }
+
+    // Topics the client is subscribed to. Each change is a single insertion or removal,
+    // so a poisoned lock still holds a consistent set.
+    pub fn subscriptions(&self) -> MutexGuard<'_, BTreeSet<String>> {
+        self.subscriptions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn info(&self, id: ConnectionId) -> ConnectionInfo {
+        let stats = &self.stats;
+        let last_activity = Duration::from_micros(stats.last_activity.load(Ordering::Relaxed));
+        ConnectionInfo {
+            id,
+            peer_addr: self.peer_addr.clone(),
+            connected_at: stats.connected_at,
+            last_activity: stats.connected_at + last_activity,
+            bytes_received: stats.bytes_received.load(Ordering::Relaxed),
+            bytes_sent: stats.bytes_sent.load(Ordering::Relaxed),
+            requests: stats.requests.load(Ordering::Relaxed),
+            failed_requests: stats.failed_requests.load(Ordering::Relaxed),
+            subscriptions: self.subscriptions().iter().cloned().collect(),
+        }
+    }
+}
+
+// Connections currently being served, keyed by connection id. Each connection stays
+// registered for as long as its `Registration` is alive.
+pub(crate) struct Registry {
+    connections: Mutex<HashMap<ConnectionId, Arc<Connection>>>, // Registered connections
+    next_id: AtomicU64,                                         // Id given to the next connection
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry {
+            connections: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<ConnectionId, Arc<Connection>>> {
+        // Entries are inserted and removed whole, so a poisoned lock still holds consistent state
+        self.connections.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Registers a connection under a new id, keeping a handle on its stream so the server
+    // can shut it down, and the half of the connection written to. The connection is
+    // deregistered when the registration is dropped.
+    pub fn register(self: &Arc<Self>, stream: &Stream, writer: Writer) -> io::Result<Registration> {
+        let connection = Arc::new(Connection {
+            stream: stream.try_clone()?,
+            writer: Mutex::new(writer),
+            peer_addr: stream.peer_addr()?,
+            stats: ConnectionStats::new(),
+            subscriptions: Mutex::new(BTreeSet::new()),
+        });
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.lock().insert(id, Arc::clone(&connection));
+        Ok(Registration {
+            registry: Arc::clone(self),
+            id,
+            connection,
+        })
+    }
+
+    // Number of connections registered
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    // Snapshot of every registered connection, ordered by id
+    pub fn snapshot(&self) -> Vec<ConnectionInfo> {
+        let mut connections: Vec<_> = self.lock().iter().map(|(&id, connection)| connection.info(id)).collect();
+        connections.sort_by_key(|info| info.id);
+        connections
+    }
+
+    // Looks up a registered connection
+    pub fn get(&self, id: ConnectionId) -> Option<Arc<Connection>> {
+        self.lock().get(&id).cloned()
+    }
+
+    // Every registered connection with its id
+    pub fn all(&self) -> Vec<(ConnectionId, Arc<Connection>)> {
+        self.lock().iter().map(|(&id, connection)| (id, Arc::clone(connection))).collect()
+    }
+
+    // Shuts a connection down, returning false if no connection has the given id
+    pub fn shutdown(&self, id: ConnectionId, how: Shutdown) -> bool {
+        match self.lock().get(&id) {
+            Some(connection) => {
+                if let Err(e) = connection.stream.shutdown(how) {
+                    warn!("Error shutting down connection {}: {}", id, e);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Shuts down the given halves of every registered connection
+    pub fn shutdown_all(&self, how: Shutdown) {
+        for (id, connection) in self.lock().iter() {
+            if let Err(e) = connection.stream.shutdown(how) {
+                // The client may have disconnected without its handler noticing yet
+                warn!("Error shutting down connection {}: {}", id, e);
+            }
+        }
+    }
+}
+
+// Keeps a connection registered until dropped, which happens even if its handler panics
+pub(crate) struct Registration {
+    registry: Arc<Registry>,
+    id: ConnectionId,
+    connection: Arc<Connection>,
+}
+
+impl Registration {
+    pub fn id(&self) -> ConnectionId {
+        self.id
+    }
+
+    // The registered connection, through which the worker writes to the client
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.registry.lock().remove(&self.id);
+    }
+}
@@ -1,90 +1,235 @@
-use crate::message::{client_message, server_message, AddResponse, ClientMessage, EchoMessage, ServerMessage};
+use crate::config::{SaturationPolicy, ServerConfig};
+use crate::framing::{encode_frame, write_frame};
+use crate::handler::{error_response, AddHandler, Address, ConnectionContext, ConnectionId, EchoHandler, Handler};
+use crate::message::{ErrorCode, ServerMessage};
+use crate::pool::{Job, ThreadPool};
+use crate::rate_limit::{PeerLimits, RateLimiter};
+use crate::registry::{ConnectionInfo, Registration, Registry};
+use crate::session::{goodbye, response_to, Session};
+use crate::topics::TopicHandler;
+use crate::transport::{Listener, Reader, Stream, Transport};
 use log::{error, info, warn};
-use prost::Message;
+use socket2::{Domain, Protocol, Socket, Type};
 use std::{
-    io::{self, Read, Write},
-    net::{TcpListener, TcpStream},
-    sync::{Arc, Mutex},
+    io::{self, Read},
+    net::{Shutdown, SocketAddr, TcpListener},
+    sync::{Arc, Condvar, Mutex},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+// Backlog of pending connections requested when the listener starts listening
+const LISTEN_BACKLOG: i32 = 128;
+
+// Longest `stop` waits for the connection that wakes the accept loop
+const WAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Pauses of the accept loops of both servers after a failed accept, doubling from the first
+// to the last while accepts keep failing, so running out of file descriptors doesn't turn
+// them into busy loops
+const ACCEPT_BACKOFF_INITIAL: Duration = Duration::from_millis(5);
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_millis(500);
 
 // Represents a connected client
 struct Client {
-    stream: TcpStream,
+    stream: Stream,             // Socket of the connection, used to apply timeouts and shut it down
+    reader: Reader,             // Half of the connection requests are read from
+    registration: Registration, // Keeps the client listed while it is served
+    session: Session,           // Protocol state of the connection
+    config: ServerConfig, // Settings of the server the client connected to
 }
 
 impl Client {
-    // Creates a new client instance from a TCP stream
-    pub fn new(stream: TcpStream) -> Self {
-        Client { stream }
+    // Creates a new client instance from an accepted stream and the reading half of its transport
+    pub fn new(
+        stream: Stream,
+        reader: Reader,
+        registration: Registration,
+        handlers: Vec<Arc<dyn Handler>>,
+        peer_limits: Option<&Arc<PeerLimits>>,
+        config: ServerConfig,
+    ) -> io::Result<Self> {
+        // Some platforms hand out accepted streams in the listener's non-blocking mode
+        stream.set_nonblocking(false)?;
+        stream.set_write_timeout(config.write_timeout)?;
+
+        let context = ConnectionContext {
+            connection_id: registration.id(),
+            peer_addr: stream.peer_addr()?,
+            local_addr: stream.local_addr()?,
+        };
+        let rate_limiter = RateLimiter::new(&config, peer_limits, &context.peer_addr);
+        Ok(Client {
+            stream,
+            reader,
+            registration,
+            session: Session::new(context, handlers, &config, rate_limiter),
+            config,
+        })
     }
 
-    // Handles communication with the client
+    // Handles communication with the client until it disconnects, a deadline passes, or the
+    // server stops. A stopping server has `stop` shut the read half of the stream down, so
+    // requests already received are still read and answered before the client is told
+    // goodbye.
     pub fn handle(&mut self, is_running: &Arc<AtomicBool>) -> io::Result<()> {
-        let mut buffer = [0; 1024]; // Buffer to store incoming data
+        let mut buffer = vec![0; self.config.read_buffer_size]; // Buffer to store incoming data
+        let mut last_activity = Instant::now(); // When the client last sent data
+        let mut partial_since: Option<Instant> = None; // When the buffered partial message started
+        let mut draining = false; // Whether the read half was shut down for the server to stop
+
+        // Continuously read and process data until the read half is shut down. Reads block
+        // until data arrives, the client disconnects, a deadline passes, or `Server::stop`
+        // shuts the stream down, so an idle connection costs no CPU time. Once shut down,
+        // reads return whatever the client sent before, then the end of the stream.
+        loop {
+            if !draining && !is_running.load(Ordering::SeqCst) {
+                // `stop` shuts down the clients registered when it was called; one registered
+                // after that is shut down here
+                let _ = self.stream.shutdown(Shutdown::Read);
+                draining = true;
+            }
+
+            // A partially received message must be completed within the read timeout, and a
+            // silent client is dropped after the idle timeout, and a client that hasn't
+            // authenticated by its deadline is dropped whatever it sends
+            let deadline = match partial_since {
+                Some(started) => self.config.read_timeout.map(|timeout| (started + timeout, "read")),
+                None => self.config.idle_timeout.map(|timeout| (last_activity + timeout, "idle")),
+            };
+            let auth_deadline = self.session.auth_deadline().map(|deadline| (deadline, "authentication"));
+            let deadline = deadline.into_iter().chain(auth_deadline).min_by_key(|&(deadline, _)| deadline);
+            let timeout = match deadline {
+                Some((deadline, kind)) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        info!("Closing connection from {}: {} timeout expired", self.session.context().peer_addr, kind);
+                        return self.stream.shutdown(Shutdown::Both);
+                    }
+                    Some(remaining)
+                }
+                None => None,
+            };
+            self.stream.set_read_timeout(timeout)?;
 
-        // Continuously read and process data while the server is running
-        while is_running.load(Ordering::SeqCst) {
-            match self.stream.read(&mut buffer) {
-                Ok(bytes_read) if bytes_read == 0 => {
+            match self.reader.read(&mut buffer) {
+                Ok(0) if is_running.load(Ordering::SeqCst) => {
                     // Client disconnected
                     info!("Client disconnected.");
+                    return Ok(());
+                }
+                Ok(0) => {
+                    // The read half was shut down by `stop` and everything received before
+                    // has been answered
                     break;
                 }
                 Ok(bytes_read) => {
                     // Successfully read data from the client
                     info!("Received {} bytes from client", bytes_read);
+                    last_activity = Instant::now();
+                    self.registration.connection().stats().record_received(bytes_read);
 
-                    // Decode the received message
-                    if let Ok(message) = ClientMessage::decode(&buffer[..bytes_read]) {
-                        if let Some(payload) = message.message {
-                            match payload {
-                                // Handle EchoMessage: Respond with the same content
-                                client_message::Message::EchoMessage(echo) => {
-                                    let response = ServerMessage {
-                                        message: Some(server_message::Message::EchoMessage(EchoMessage {
-                                            content: echo.content,
-                                        })),
-                                    };
-                                    self.stream.write_all(&response.encode_to_vec())?;
-                                    info!("Sent EchoMessage response");
-                                }
-                                // Handle AddRequest: Respond with the sum of `a` and `b`
-                                client_message::Message::AddRequest(add) => {
-                                    let response = ServerMessage {
-                                        message: Some(server_message::Message::AddResponse(AddResponse {
-                                            result: add.a + add.b,
-                                        })),
-                                    };
-                                    self.stream.write_all(&response.encode_to_vec())?;
-                                    info!("Sent AddResponse");
-                                }
-                            }
-                        }
+                    // Answer every complete message received so far
+                    let received = self.session.receive(&buffer[..bytes_read]);
+                    self.send_responses(&received.responses)?;
+                    if received.close {
+                        return self.stream.shutdown(Shutdown::Both);
                     }
+
+                    // Start the read timeout when a message is left incomplete
+                    partial_since = if self.session.has_partial_message() {
+                        partial_since.or(Some(last_activity))
+                    } else {
+                        None
+                    };
+                }
+                Err(ref e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    // A deadline passed: the next iteration closes the connection
                 }
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    // Non-blocking mode: No data available, sleep briefly
-                    thread::sleep(Duration::from_millis(10));
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if !is_running.load(Ordering::SeqCst) => {
+                    // A shut down read half may surface as an error, such as TLS reporting
+                    // the stream ended without the peer closing the session
+                    info!("Stopped reading from {}: {}", self.session.context().peer_addr, e);
+                    break;
                 }
                 Err(e) => {
                     // Error occurred while reading from the client
                     error!("Error reading from client: {}", e);
-                    break;
+                    return Ok(());
                 }
             }
         }
+
+        // The server is stopping and every complete request has been answered. A message
+        // still partially received at this point is dropped.
+        if let Err(e) = self.registration.connection().write(&encode_frame(&goodbye())) {
+            info!("Could not say goodbye to {}: {}", self.session.context().peer_addr, e);
+        }
+        self.stream.shutdown(Shutdown::Both)
+    }
+
+    // Sends responses to the client, coalesced into a single write
+    fn send_responses(&mut self, responses: &[ServerMessage]) -> io::Result<()> {
+        if responses.is_empty() {
+            return Ok(());
+        }
+
+        let buffer: Vec<u8> = responses.iter().flat_map(encode_frame).collect();
+        let connection = self.registration.connection();
+        connection.write(&buffer)?;
+        connection.stats().record_responses(responses);
+        info!("Sent {} responses", responses.len());
         Ok(())
     }
 }
 
+// Counts a client as being served from the moment its job is built until the job has run,
+// or has been dropped without running because the worker pool turned it away
+struct ActiveClient(Arc<AtomicUsize>);
+
+impl ActiveClient {
+    fn new(active_clients: Arc<AtomicUsize>) -> Self {
+        active_clients.fetch_add(1, Ordering::SeqCst);
+        ActiveClient(active_clients)
+    }
+}
+
+impl Drop for ActiveClient {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Outcome of stopping the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ShutdownSummary {
+    pub connections_closed: usize, // Connections open when the server began stopping
+    pub connections_forced: usize, // Of those, connections cut off before their requests were answered
+    pub elapsed: Duration,         // Time from the stop request to every worker exiting
+}
+
+// Progress of `run`, which `stop` waits on
+enum RunState {
+    Idle,                                  // `run` hasn't been called
+    Running,                               // `run` is accepting connections
+    Stopping(Instant, ShutdownSummary),    // A stop was requested at the given time
+    Stopped(ShutdownSummary),              // `run` has returned
+}
+
 // Represents the server that listens for client connections
 pub struct Server {
-    listener: TcpListener,                    // Listener for incoming connections
+    listener: Listener,                       // Listener for incoming connections, TCP or Unix
     is_running: Arc<AtomicBool>,              // Atomic flag to track server state
-    clients: Arc<Mutex<Vec<TcpStream>>>,      // List of connected clients
+    clients: Arc<Registry>,                   // Connections being served, keyed by connection id
+    active_clients: Arc<AtomicUsize>,         // Number of clients currently being served
+    run_state: Mutex<RunState>,               // Whether `run` has started or finished
+    stopped: Condvar,                         // Signalled when `run` returns
+    handlers: Vec<Arc<dyn Handler>>,          // Request handlers, most recently added first
+    transport: Arc<Transport>,                // Plain TCP or TLS, as configured
+    peer_limits: Option<Arc<PeerLimits>>,     // Rate limits shared by connections from the same IP
+    config: ServerConfig,                     // Settings the server was created with
 }
 
 impl Server {
@@ -96,72 +241,327 @@ impl Server {
 
     // Creates a new server on the specified port
     pub fn new_with_port(port: u16) -> Result<Self, io::Error> {
-        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        Self::with_config(ServerConfig::default().port(port))
+    }
+
+    // Creates a new server with the given settings
+    pub fn with_config(config: ServerConfig) -> Result<Self, io::Error> {
+        if config.read_buffer_size == 0 || config.worker_threads == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Read buffer size and worker thread count must be non-zero",
+            ));
+        }
+        config.validate_rate_limits()?;
+
+        let transport = Arc::new(Transport::from_config(&config)?);
+        let listener = Listener::bind(&config)?;
+        let clients = Arc::new(Registry::new());
         Ok(Self {
             listener,
             is_running: Arc::new(AtomicBool::new(true)),
-            clients: Arc::new(Mutex::new(Vec::new())),
+            handlers: vec![
+                Arc::new(EchoHandler),
+                Arc::new(AddHandler),
+                Arc::new(TopicHandler::new(Arc::clone(&clients), &config)),
+            ],
+            clients,
+            transport,
+            peer_limits: PeerLimits::from_config(&config),
+            active_clients: Arc::new(AtomicUsize::new(0)),
+            run_state: Mutex::new(RunState::Idle),
+            stopped: Condvar::new(),
+            config,
         })
     }
 
-    // Starts the server and listens for incoming client connections
+    // Adds a request handler. Handlers added later are consulted first, so they can take
+    // over requests from earlier handlers and from the built-in Echo, Add and
+    // publish/subscribe handlers.
+    pub fn with_handler<H: Handler + 'static>(mut self, handler: H) -> Self {
+        self.handlers.insert(0, Arc::new(handler));
+        self
+    }
+
+    // Starts the server and listens for incoming client connections. Connections are
+    // served by a fixed pool of worker threads; `run` returns once the server is stopped
+    // and every worker has finished.
     pub fn run(&self) -> io::Result<()> {
-        self.is_running.store(true, Ordering::SeqCst); // Mark the server as running
+        // The server is marked as running on creation, so a `stop` issued before `run`
+        // is reached is not lost
+        *self.run_state.lock().unwrap() = RunState::Running;
+        let result = self.serve();
+
+        // Wake `stop` callers even if the server failed to start
+        let mut run_state = self.run_state.lock().unwrap();
+        let summary = match *run_state {
+            RunState::Stopping(since, summary) => ShutdownSummary {
+                elapsed: since.elapsed(),
+                ..summary
+            },
+            _ => ShutdownSummary::default(),
+        };
+        info!("Server stopped: {:?}", summary);
+        *run_state = RunState::Stopped(summary);
+        self.stopped.notify_all();
+        result
+    }
+
+    // Accepts connections until the server is stopped, then drains them
+    fn serve(&self) -> io::Result<()> {
+        // Accept blocks until a client connects; `stop` wakes it with a connection of its own
+        self.listener.set_nonblocking(false)?;
 
-        // Set the listener to non-blocking mode
-        self.listener.set_nonblocking(true)?;
+        let pool = ThreadPool::new("client", self.config.worker_threads, self.config.accept_queue_size)?;
 
+        let mut accept_backoff = None;
         while self.is_running.load(Ordering::SeqCst) {
             match self.listener.accept() {
                 Ok((stream, addr)) => {
+                    accept_backoff = None;
+                    if !self.is_running.load(Ordering::SeqCst) {
+                        // Woken up by `stop`
+                        break;
+                    }
+
                     // New client connection accepted
                     info!("New client connected: {}", addr);
 
-                    // Add the client stream to the list of connected clients
-                    let mut clients = self.clients.lock().unwrap();
-                    clients.push(stream.try_clone()?);
-                    drop(clients); // Release the mutex before spawning a thread
-
-                    // Spawn a new thread to handle the client
-                    let is_running = Arc::clone(&self.is_running);
-                    let _ = thread::spawn(move || {
-                        let mut client = Client::new(stream);
-                        if let Err(e) = client.handle(&is_running) {
-                            error!("Error handling client: {}", e);
+                    // Turn the client away if the server is already serving as many as it may
+                    if let Some(max_clients) = self.config.max_clients {
+                        if self.active_clients.load(Ordering::SeqCst) >= max_clients {
+                            warn!("Rejecting client {}: {} clients already connected", addr, max_clients);
+                            self.reject_client(stream, &format!("Server is serving its maximum of {} clients", max_clients));
+                            continue;
                         }
-                    });
-                }
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    // Non-blocking mode: No incoming connections, sleep briefly
-                    thread::sleep(Duration::from_millis(100));
+                    }
+
+                    // Only this thread queues jobs, so a pool with room keeps it until the
+                    // job is queued
+                    if self.config.saturation_policy == SaturationPolicy::Reject && pool.is_full() {
+                        warn!("Rejecting client {}: all workers are busy", addr);
+                        self.reject_client(stream, "All workers are busy");
+                        continue;
+                    }
+
+                    let job = self.client_job(stream);
+                    let queued = match self.config.saturation_policy {
+                        SaturationPolicy::Reject => pool.try_execute(job),
+                        SaturationPolicy::Wait => pool.execute(job),
+                    };
+                    if queued.is_err() {
+                        // The pool only turns jobs away once closed. Dropping the job closes
+                        // the connection and stops counting the client as served.
+                        warn!("Dropping client {}: the worker pool is closed", addr);
+                    }
                 }
                 Err(e) => {
-                    // Error occurred while accepting a connection
-                    warn!("Error accepting connection: {}", e);
+                    // Error occurred while accepting a connection. Errors such as running out
+                    // of file descriptors persist for a while, so wait before trying again.
+                    let backoff = next_accept_backoff(accept_backoff);
+                    warn!("Error accepting connection, retrying in {:?}: {}", backoff, e);
+                    thread::sleep(backoff);
+                    accept_backoff = Some(backoff);
                 }
             }
         }
 
-        info!("Server stopped.");
+        // Give the clients until the deadline to have their requests answered, then cut off
+        // the ones still being served so the workers finish promptly
+        if !pool.wait_idle(self.config.shutdown_timeout) {
+            warn!("Shutdown timeout expired, closing remaining connections");
+            self.close_clients();
+        }
+        pool.join();
         Ok(())
     }
 
-    // Stops the server and disconnects all clients
-    pub fn stop(&self) {
-        self.is_running.store(false, Ordering::SeqCst); // Mark the server as stopped
+    // Builds the job a worker runs to serve a client
+    fn client_job(&self, stream: Stream) -> Job {
+        let is_running = Arc::clone(&self.is_running);
+        let clients = Arc::clone(&self.clients);
+        let active_client = ActiveClient::new(Arc::clone(&self.active_clients));
+        let handlers = self.handlers.clone();
+        let transport = Arc::clone(&self.transport);
+        let peer_limits = self.peer_limits.clone();
+        let config = self.config.clone();
+
+        Box::new(move || {
+            let _active_client = active_client; // Moved into the job, so dropped whether it runs or not
+
+            // Register the client for as long as it is served. `stop` clears the running flag
+            // before shutting down the registered clients, so a client registered after that
+            // sees the flag and says goodbye at once.
+            let result = transport.split(&stream).and_then(|(reader, writer)| {
+                let registration = clients.register(&stream, writer)?;
+                Client::new(stream, reader, registration, handlers, peer_limits.as_ref(), config)?.handle(&is_running)
+            });
+            if let Err(e) = result {
+                error!("Error handling client: {}", e);
+            }
+        })
+    }
 
-        // Disconnect all clients
-        for client in self.clients.lock().unwrap().drain(..) {
-            if let Err(e) = client.shutdown(std::net::Shutdown::Both) {
-                warn!("Error shutting down client: {}", e);
+    // Tells a client the server is too busy to serve it and closes its connection. TLS
+    // clients are disconnected without an explanation, as answering them would need a
+    // handshake on the accept thread.
+    fn reject_client(&self, mut stream: Stream, detail: &str) {
+        if self.transport.is_plain() {
+            let response = response_to(0, error_response(ErrorCode::ServerBusy, detail.to_string()));
+            if let Err(e) = write_frame(&mut stream, &response) {
+                warn!("Error rejecting client: {}", e);
             }
         }
+        let _ = stream.shutdown(Shutdown::Both);
+    }
 
+    // Stops the server gracefully: stops accepting connections, answers the requests
+    // clients have already sent, tells each client goodbye and waits for every worker to
+    // exit. Connections still being served after the configured shutdown timeout are
+    // closed forcibly. Returns at once if `run` was never called.
+    pub fn stop(&self) -> ShutdownSummary {
+        self.begin_stop();
         info!("Server shutting down...");
+
+        // Shutting down the read half wakes clients waiting for a request while leaving
+        // the data already received to be read and answered
+        self.clients.shutdown_all(Shutdown::Read);
+        self.wake_accept_loop();
+
+        let mut run_state = self.run_state.lock().unwrap();
+        loop {
+            match *run_state {
+                RunState::Idle => return ShutdownSummary::default(),
+                RunState::Running | RunState::Stopping(..) => run_state = self.stopped.wait(run_state).unwrap(),
+                RunState::Stopped(summary) => return summary,
+            }
+        }
+    }
+
+    // Stops the server at once, disconnecting every client without waiting for in-flight
+    // requests. Doesn't wait for `run` to return; may be used to cut a `stop` short.
+    pub fn force_stop(&self) {
+        self.begin_stop();
+        info!("Server shutting down forcibly...");
+
+        self.close_clients();
+        self.wake_accept_loop();
+    }
+
+    // Marks the server as stopped, noting the connections open at the time
+    fn begin_stop(&self) {
+        let mut run_state = self.run_state.lock().unwrap();
+        self.is_running.store(false, Ordering::SeqCst);
+        if let RunState::Running = *run_state {
+            let summary = ShutdownSummary {
+                connections_closed: self.active_clients.load(Ordering::SeqCst),
+                ..Default::default()
+            };
+            *run_state = RunState::Stopping(Instant::now(), summary);
+        }
+    }
+
+    // Disconnects every client still being served
+    fn close_clients(&self) {
+        if let RunState::Stopping(_, summary) = &mut *self.run_state.lock().unwrap() {
+            let active_clients = self.active_clients.load(Ordering::SeqCst);
+            summary.connections_forced = summary.connections_forced.max(active_clients).min(summary.connections_closed);
+        }
+        self.clients.shutdown_all(Shutdown::Both);
     }
 
-    // Retrieves the port the server is listening on
+    // Wakes the accept loop, which is blocked waiting for a connection, by connecting to
+    // the listener so `accept` returns and notices the server stopped
+    fn wake_accept_loop(&self) {
+        if let Err(e) = self.listener.wake(WAKE_TIMEOUT) {
+            warn!("Error waking the accept loop: {}", e);
+        }
+    }
+
+    // Number of connections currently being served. Clients waiting for a free worker
+    // aren't counted until a worker picks them up.
+    pub fn connection_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    // Lists the connections currently being served
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        self.clients.snapshot()
+    }
+
+    // Disconnects a client without waiting for its in-flight requests, returning false if
+    // no connection has the given id
+    pub fn disconnect(&self, id: ConnectionId) -> bool {
+        info!("Disconnecting connection {}", id);
+        self.clients.shutdown(id, Shutdown::Both)
+    }
+
+    // Pushes a message to one client, failing with `NotFound` if no connection has the
+    // given id. Pushed messages normally carry request id 0, as they answer no request.
+    pub fn send_to(&self, id: ConnectionId, message: &ServerMessage) -> io::Result<()> {
+        let connection = self.clients.get(id).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("No connection with id {}", id))
+        })?;
+        connection.write(&encode_frame(message))
+    }
+
+    // Pushes a message to every connected client, returning how many it reached. Clients
+    // the message can't be written to are disconnected.
+    pub fn broadcast(&self, message: &ServerMessage) -> usize {
+        let frame = encode_frame(message);
+        let mut reached = 0;
+        for (id, connection) in self.clients.all() {
+            match connection.write(&frame) {
+                Ok(()) => reached += 1,
+                Err(e) => warn!("Error broadcasting to connection {}: {}", id, e),
+            }
+        }
+        reached
+    }
+
+    // Retrieves the port the server is listening on, failing with `Unsupported` for a
+    // server listening on a Unix domain socket
     pub fn get_port(&self) -> Result<u16, io::Error> {
-        self.listener.local_addr().map(|addr| addr.port())
+        self.local_addr().map(|addr| addr.port())
+    }
+
+    // Retrieves the address the server is listening on, failing with `Unsupported` for a
+    // server listening on a Unix domain socket
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        match self.listener.local_addr()? {
+            Address::Tcp(address) => Ok(address),
+            #[cfg(unix)]
+            Address::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Server is listening on a Unix domain socket",
+            )),
+        }
+    }
+
+    // Retrieves the settings the server was created with
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+}
+
+// Pause after a failed accept, given the pause after the previous one if accepts have kept
+// failing since
+pub(crate) fn next_accept_backoff(previous: Option<Duration>) -> Duration {
+    previous.map_or(ACCEPT_BACKOFF_INITIAL, |backoff| (backoff * 2).min(ACCEPT_BACKOFF_MAX))
+}
+
+// Creates the listening socket described by the configuration. IPv6 listeners are
+// switched between IPv6-only and dual-stack explicitly rather than relying on the
+// system default.
+pub(crate) fn bind_listener(config: &ServerConfig) -> io::Result<TcpListener> {
+    let address = SocketAddr::new(config.bind_address, config.port);
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, Some(Protocol::TCP))?;
+    if address.is_ipv6() {
+        socket.set_only_v6(!config.dual_stack)?;
     }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?; // Matches `TcpListener::bind`, allowing quick restarts
+    socket.bind(&address.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    Ok(socket.into())
 }
@@ -0,0 +1,175 @@
+use crate::auth::{self, AuthState};
+use crate::config::ServerConfig;
+use crate::framing::{FrameBuffer, FrameTooLarge};
+use crate::handler::{error_response, ConnectionContext, Handler};
+use crate::rate_limit::{rate_limited, RateLimiter};
+use crate::message::{client_message, server_message, ClientMessage, ErrorCode, Goodbye, Pong, ServerMessage};
+use log::{error, warn};
+use prost::Message;
+use std::{sync::Arc, time::Instant};
+
+// Outcome of feeding received bytes to a session
+#[derive(Debug, Default)]
+pub(crate) struct Received {
+    pub responses: Vec<ServerMessage>, // Responses to send, in request order
+    pub close: bool,                   // Close the connection once the responses are sent
+}
+
+// Protocol state of one connection. Turns the bytes a client sends into the responses to
+// send back, independent of the transport and I/O model serving the connection.
+pub(crate) struct Session {
+    context: ConnectionContext,      // Connection details passed to handlers
+    handlers: Vec<Arc<dyn Handler>>, // Handlers offered each request, in order
+    frames: FrameBuffer,             // Reassembles frames split across or coalesced within reads
+    auth: Option<AuthState>,         // Authentication progress, if the server requires it
+    rate_limiter: RateLimiter,       // Tokens the client's requests are paid from
+}
+
+impl Session {
+    // Creates the session for a newly accepted connection
+    pub fn new(
+        context: ConnectionContext,
+        handlers: Vec<Arc<dyn Handler>>,
+        config: &ServerConfig,
+        rate_limiter: RateLimiter,
+    ) -> Self {
+        Session {
+            context,
+            handlers,
+            frames: FrameBuffer::with_max_frame_size(config.max_message_size),
+            auth: AuthState::new(config),
+            rate_limiter,
+        }
+    }
+
+    // Details of the connection this session serves
+    pub fn context(&self) -> &ConnectionContext {
+        &self.context
+    }
+
+    // When the client must have authenticated by, if the server requires it and the client
+    // hasn't yet
+    pub fn auth_deadline(&self) -> Option<Instant> {
+        self.auth.as_ref().and_then(AuthState::deadline)
+    }
+
+    // Returns true if part of a message has been received but not the rest of it
+    pub fn has_partial_message(&self) -> bool {
+        !self.frames.is_empty()
+    }
+
+    // Processes bytes received from the client, answering every complete message
+    pub fn receive(&mut self, data: &[u8]) -> Received {
+        self.frames.extend(data);
+
+        let mut received = Received::default();
+        loop {
+            match self.frames.next_frame() {
+                Ok(Some(frame)) => {
+                    received.responses.push(self.handle_frame(&frame));
+                    if self.auth.as_ref().is_some_and(AuthState::is_exhausted) {
+                        warn!("Closing connection from {}: too many failed authentication attempts", self.context.peer_addr);
+                        received.close = true;
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    if let Some(too_large) = FrameTooLarge::from_io_error(&e) {
+                        // Reject the message before buffering it and drop the client
+                        warn!("Rejecting oversized message: {}", too_large);
+                        let error = error_response(ErrorCode::MessageTooLarge, too_large.to_string());
+                        received.responses.push(response_to(0, error));
+                    } else {
+                        // The stream can't be resynchronised after a corrupt length prefix
+                        error!("Invalid frame from client: {}", e);
+                    }
+                    received.close = true;
+                    break;
+                }
+            }
+        }
+        received
+    }
+
+    // Decodes a single frame payload and builds the response to the request it contains.
+    // Every frame gets exactly one response so the client never waits for a reply that
+    // won't come.
+    fn handle_frame(&mut self, frame: &[u8]) -> ServerMessage {
+        let message = match ClientMessage::decode(frame) {
+            Ok(message) => message,
+            Err(e) => {
+                // The request id can't be recovered from an undecodable frame
+                warn!("Failed to decode ClientMessage: {}", e);
+                let error = error_response(ErrorCode::MalformedRequest, format!("Failed to decode request: {}", e));
+                return response_to(0, error);
+            }
+        };
+        let request_id = message.request_id;
+
+        // Every request counts against the rate limits, pings and failed requests included
+        if let Err(retry_after) = self.rate_limiter.check() {
+            warn!("Rate limiting request {} from {}", request_id, self.context.peer_addr);
+            return response_to(request_id, rate_limited(retry_after));
+        }
+
+        let payload = match message.message {
+            Some(payload) => payload,
+            // Unknown oneof arms are skipped while decoding, so a frame without a payload
+            // but with fields other than the request id carries an unsupported request type
+            None if frame.len() == (ClientMessage { message: None, request_id }).encoded_len() => {
+                warn!("Received an empty ClientMessage");
+                let error = error_response(ErrorCode::EmptyRequest, "Request carries no message".to_string());
+                return response_to(request_id, error);
+            }
+            None => {
+                warn!("Received an unsupported ClientMessage");
+                let error = error_response(ErrorCode::UnsupportedRequest, "Unsupported request type".to_string());
+                return response_to(request_id, error);
+            }
+        };
+
+        // Keepalives are answered by the session itself so handlers can't interfere with them
+        if let client_message::Message::Ping(ping) = payload {
+            return response_to(request_id, server_message::Message::Pong(Pong { payload: ping.payload }));
+        }
+
+        // So is authentication, which has to succeed before any other request is handled
+        if let client_message::Message::AuthRequest(ref request) = payload {
+            let response = match self.auth {
+                Some(ref mut auth) => auth.authenticate(request),
+                None => auth::authenticated(),
+            };
+            return response_to(request_id, response);
+        }
+        if let Some(auth) = self.auth.as_mut().filter(|auth| !auth.is_authenticated()) {
+            warn!("Rejecting request {} from an unauthenticated connection", request_id);
+            return response_to(request_id, auth.refuse());
+        }
+
+        // Reply with the response of the first handler that accepts the request
+        let response = self
+            .handlers
+            .iter()
+            .find_map(|handler| handler.handle(&payload, &self.context))
+            .unwrap_or_else(|| {
+                warn!("No handler for request {}", request_id);
+                error_response(ErrorCode::UnsupportedRequest, "Unsupported request type".to_string())
+            });
+        response_to(request_id, response)
+    }
+}
+
+// Wraps a response payload in a message tagged with the id of the request it answers
+pub(crate) fn response_to(request_id: u64, message: server_message::Message) -> ServerMessage {
+    ServerMessage {
+        message: Some(message),
+        request_id,
+    }
+}
+
+// Builds the message that tells a client the server closes its connection to shut down
+pub(crate) fn goodbye() -> ServerMessage {
+    let reason = "Server is shutting down".to_string();
+    response_to(0, server_message::Message::Goodbye(Goodbye { reason }))
+}
@@ -0,0 +1,373 @@
+use crate::calls;
+use crate::client::{Client, ClientError, Connection, Endpoint, Result, MAX_HELD_MESSAGES, READ_BUFFER_SIZE};
+use crate::config::ClientConfig;
+use crate::framing::{encode_frame, FrameBuffer, FrameTooLarge};
+use crate::message::{client_message, server_message, ClientMessage, OverflowMode, ServerMessage};
+use crate::transport::{Reader, Stream, Writer};
+use log::{debug, info, warn};
+use prost::Message;
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, Read, Write},
+    net::Shutdown,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex, MutexGuard,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+// Handle to a client connection that many threads can use at once. Clones share the
+// connection: requests from any of them are written whole one at a time, and a background
+// thread reads the responses and hands each to the caller awaiting its request id.
+// Messages that answer no pending request, such as events and the goodbye, are returned
+// by `receive`. Up to 1024 of them are held until then; further ones are dropped with a
+// warning, so a subscription nobody receives from can't use up memory. A lost connection
+// isn't re-established; every call fails once it is gone.
+#[derive(Clone)]
+pub struct SharedClient {
+    inner: Arc<Inner>,
+}
+
+// Connection state shared by the handles
+struct Inner {
+    endpoint: Endpoint,                             // Server the connection is to
+    stream: Stream,                                 // Socket, shut down once the last handle is dropped
+    writer: Mutex<Writer>,                          // Held while a request is written
+    next_request_id: AtomicU64,                     // Id assigned to the next request
+    read_timeout: Option<Duration>,                 // Longest a caller waits for a response or message
+    router: Arc<Router>,                            // Callers awaiting responses, shared with the reader
+    messages: Mutex<mpsc::Receiver<ServerMessage>>, // Messages answering no pending request
+}
+
+impl SharedClient {
+    // Connects to the endpoint with the given settings and returns a handle to the
+    // connection. To authenticate or connect to redundant servers first, set up a `Client`
+    // and call `Client::into_shared`.
+    pub fn connect(endpoint: impl Into<Endpoint>, config: ClientConfig) -> Result<Self> {
+        let mut client = Client::with_config(endpoint, config);
+        client.connect()?;
+        client.into_shared()
+    }
+
+    // Takes over the open connection of a `Client`, along with the data it has read and
+    // the messages it has held over
+    pub(crate) fn new(
+        endpoint: Endpoint,
+        config: &ClientConfig,
+        connection: Connection,
+        frames: FrameBuffer,
+        next_request_id: u64,
+        pending: VecDeque<ServerMessage>,
+    ) -> Result<Self> {
+        // The reader waits for as long as the connection lasts; callers time out on their own
+        connection.stream.set_read_timeout(None)?;
+
+        let router = Arc::new(Router::default());
+        let (sender, messages) = mpsc::sync_channel(MAX_HELD_MESSAGES);
+        let mut unrequested = Unrequested { sender, dropping: false };
+        for message in pending {
+            unrequested.hold(message);
+        }
+        let reader_router = Arc::clone(&router);
+        let reader_endpoint = endpoint.clone();
+        let reader = connection.reader;
+        thread::Builder::new()
+            .name("client-reader".to_string())
+            .spawn(move || read_messages(reader, frames, &reader_router, &mut unrequested, &reader_endpoint))?;
+
+        Ok(SharedClient {
+            inner: Arc::new(Inner {
+                endpoint,
+                stream: connection.stream,
+                writer: Mutex::new(connection.writer),
+                next_request_id: AtomicU64::new(next_request_id),
+                read_timeout: config.read_timeout,
+                router,
+                messages: Mutex::new(messages),
+            }),
+        })
+    }
+
+    // Returns true until the connection is closed or lost
+    pub fn is_connected(&self) -> bool {
+        self.inner.router.lock().closed.is_none()
+    }
+
+    // Endpoint the connection is to
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.inner.endpoint
+    }
+
+    // Closes the connection for every handle, failing requests still awaiting a response
+    pub fn disconnect(&self) -> Result<()> {
+        if !self.inner.router.close(ClientError::NotConnected) {
+            return Err(ClientError::NotConnected);
+        }
+        if let Err(e) = self.inner.stream.shutdown(Shutdown::Both) {
+            // The server may already have closed its end
+            debug!("Error shutting down connection: {}", e);
+        }
+        info!("Disconnected from server at {}", self.inner.endpoint);
+        Ok(())
+    }
+
+    // Pings the server and returns the round-trip time
+    pub fn ping(&self) -> Result<Duration> {
+        let sent_at = Instant::now();
+        let payload = self.inner.next_request_id.load(Ordering::Relaxed); // Any value works
+        calls::ping(payload).send(|message| self.request(message))?;
+        Ok(sent_at.elapsed())
+    }
+
+    // Sends an EchoMessage and returns the content the server echoed
+    pub fn echo(&self, content: impl Into<String>) -> Result<String> {
+        calls::echo(content.into()).send(|message| self.request(message))
+    }
+
+    // Adds two numbers on the server, failing with an ARITHMETIC_OVERFLOW error if the
+    // sum doesn't fit in an i32
+    pub fn add(&self, a: i32, b: i32) -> Result<i32> {
+        self.add_with_mode(a, b, OverflowMode::Checked)
+    }
+
+    // Adds two numbers on the server, handling overflow as the mode says
+    pub fn add_with_mode(&self, a: i32, b: i32, mode: OverflowMode) -> Result<i32> {
+        calls::add(a, b, mode).send(|message| self.request(message))
+    }
+
+    // Subscribes the connection to a topic and returns every topic it is subscribed to.
+    // Events are then returned by `receive` on any of the handles.
+    pub fn subscribe(&self, topic: impl Into<String>) -> Result<Vec<String>> {
+        calls::subscribe(topic.into()).send(|message| self.request(message))
+    }
+
+    // Unsubscribes from a topic and returns the topics the connection is left subscribed to
+    pub fn unsubscribe(&self, topic: impl Into<String>) -> Result<Vec<String>> {
+        calls::unsubscribe(topic.into()).send(|message| self.request(message))
+    }
+
+    // Publishes an event and returns how many subscribers it was delivered to
+    pub fn publish(&self, topic: impl Into<String>, payload: impl Into<Vec<u8>>) -> Result<u32> {
+        calls::publish(topic.into(), payload.into()).send(|message| self.request(message))
+    }
+
+    // Sends a request and waits for the response to it, returning error responses as
+    // `ClientError::Server`. A response arriving after the wait timed out is returned by
+    // `receive`.
+    pub fn request(&self, message: client_message::Message) -> Result<server_message::Message> {
+        let request_id = self.inner.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let response = self.inner.router.register(request_id)?;
+        let frame = encode_frame(&ClientMessage {
+            message: Some(message),
+            request_id,
+        });
+        if let Err(e) = self.inner.send(&frame) {
+            self.inner.router.unregister(request_id);
+            return Err(e);
+        }
+
+        let response = match wait(&response, self.inner.read_timeout) {
+            Ok(response) => response?,
+            Err(e) => {
+                self.inner.router.unregister(request_id);
+                return Err(e);
+            }
+        };
+        match response.message {
+            Some(server_message::Message::ErrorResponse(error)) => Err(ClientError::Server(error)),
+            Some(message) => Ok(message),
+            None => Err(ClientError::UnexpectedResponse(None)),
+        }
+    }
+
+    // Receives the next message that answers no pending request, such as an event, a
+    // notification or the goodbye of a stopping server. Each message goes to one caller
+    // only, whichever handle it calls through.
+    pub fn receive(&self) -> Result<ServerMessage> {
+        let messages = self.inner.messages.lock().unwrap_or_else(|e| e.into_inner());
+        match wait(&messages, self.inner.read_timeout) {
+            Err(ClientError::Disconnected) => Err(self.inner.router.closed_error()),
+            result => result,
+        }
+    }
+}
+
+impl Inner {
+    // Writes a frame, closing the connection if that fails as the server may have
+    // received part of it
+    fn send(&self, frame: &[u8]) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = writer.write_all(frame).and_then(|()| writer.flush()) {
+            let error = ClientError::from(e);
+            warn!("Connection to {} failed: {}", self.endpoint, error);
+            self.router.close(duplicate(&error));
+            let _ = self.stream.shutdown(Shutdown::Both);
+            return Err(error);
+        }
+        Ok(())
+    }
+}
+
+// Stops the reader once the last handle is gone
+impl Drop for Inner {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+// Callers awaiting responses, by request id
+#[derive(Default)]
+struct Router(Mutex<Routes>);
+
+#[derive(Default)]
+struct Routes {
+    waiting: HashMap<u64, mpsc::Sender<Result<ServerMessage>>>, // Where to send each awaited response
+    closed: Option<ClientError>,                                 // Why the connection is closed, once it is
+}
+
+impl Router {
+    fn lock(&self) -> MutexGuard<'_, Routes> {
+        // Routes are updated whole, so a poisoned lock still holds consistent state
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Registers a caller about to send a request, failing if the connection is closed
+    fn register(&self, request_id: u64) -> Result<mpsc::Receiver<Result<ServerMessage>>> {
+        let mut routes = self.lock();
+        if let Some(ref error) = routes.closed {
+            return Err(duplicate(error));
+        }
+        let (sender, receiver) = mpsc::channel();
+        routes.waiting.insert(request_id, sender);
+        Ok(receiver)
+    }
+
+    fn unregister(&self, request_id: u64) {
+        self.lock().waiting.remove(&request_id);
+    }
+
+    // Hands a message to the caller awaiting it, or returns it if nobody is
+    fn route(&self, message: ServerMessage) -> Option<ServerMessage> {
+        let sender = self.lock().waiting.remove(&message.request_id);
+        match sender {
+            Some(sender) => sender.send(Ok(message)).err().and_then(|e| e.0.ok()),
+            None => Some(message),
+        }
+    }
+
+    // Fails every awaited and future request with the error. Returns false if the
+    // connection was already closed.
+    fn close(&self, error: ClientError) -> bool {
+        let mut routes = self.lock();
+        if routes.closed.is_some() {
+            return false;
+        }
+        for (_, sender) in routes.waiting.drain() {
+            let _ = sender.send(Err(duplicate(&error)));
+        }
+        routes.closed = Some(error);
+        true
+    }
+
+    // Why the connection was closed, or `Disconnected` if that wasn't recorded
+    fn closed_error(&self) -> ClientError {
+        self.lock().closed.as_ref().map_or(ClientError::Disconnected, duplicate)
+    }
+}
+
+// Messages answering no pending request, on their way to `receive`
+struct Unrequested {
+    sender: mpsc::SyncSender<ServerMessage>,
+    dropping: bool, // Whether the last message was dropped, to warn once per run of them
+}
+
+impl Unrequested {
+    // Holds a message for `receive`, dropping it if `MAX_HELD_MESSAGES` are already
+    // held. Blocking until there is room instead would hold up the responses behind it.
+    fn hold(&mut self, message: ServerMessage) {
+        match self.sender.try_send(message) {
+            Ok(()) => self.dropping = false,
+            Err(mpsc::TrySendError::Full(_)) => {
+                if !self.dropping {
+                    warn!("Dropping messages: {} are already waiting to be received", MAX_HELD_MESSAGES);
+                }
+                self.dropping = true;
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => {} // Every handle is gone
+        }
+    }
+}
+
+// Waits for a value from the channel, up to the timeout if there is one. A channel whose
+// sender is gone means the connection is.
+fn wait<T>(receiver: &mpsc::Receiver<T>, timeout: Option<Duration>) -> Result<T> {
+    let result = match timeout {
+        Some(timeout) => receiver.recv_timeout(timeout),
+        None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+    };
+    result.map_err(|e| match e {
+        RecvTimeoutError::Timeout => ClientError::TimedOut,
+        RecvTimeoutError::Disconnected => ClientError::Disconnected,
+    })
+}
+
+// Reads messages until the connection closes, routing responses to the callers awaiting
+// them and everything else to `receive`
+fn read_messages(
+    mut reader: Reader,
+    mut frames: FrameBuffer,
+    router: &Router,
+    messages: &mut Unrequested,
+    endpoint: &Endpoint,
+) {
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+    let error = loop {
+        match frames.next_frame() {
+            Ok(Some(frame)) => match ServerMessage::decode(frame.as_slice()) {
+                Ok(message) => {
+                    if let Some(message) = router.route(message) {
+                        messages.hold(message);
+                    }
+                    continue;
+                }
+                Err(e) => break ClientError::Protocol(format!("Failed to decode ServerMessage: {}", e)),
+            },
+            Ok(None) => {}
+            Err(e) => {
+                let detail = match FrameTooLarge::from_io_error(&e) {
+                    Some(too_large) => too_large.to_string(),
+                    None => e.to_string(),
+                };
+                break ClientError::Protocol(detail);
+            }
+        }
+
+        match reader.read(&mut buffer) {
+            Ok(0) => break ClientError::Disconnected,
+            Ok(bytes_read) => frames.extend(&buffer[..bytes_read]),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => break ClientError::from(e),
+        }
+    };
+
+    if router.close(duplicate(&error)) {
+        info!("Connection to {} closed: {}", endpoint, error);
+    }
+}
+
+// Copies an error to report it to every caller it affects. I/O errors keep their kind
+// and message only.
+fn duplicate(error: &ClientError) -> ClientError {
+    match error {
+        ClientError::NotConnected => ClientError::NotConnected,
+        ClientError::Disconnected => ClientError::Disconnected,
+        ClientError::TimedOut => ClientError::TimedOut,
+        ClientError::Protocol(detail) => ClientError::Protocol(detail.clone()),
+        ClientError::Server(error) => ClientError::Server(error.clone()),
+        ClientError::UnexpectedResponse(message) => ClientError::UnexpectedResponse(message.clone()),
+        ClientError::Io(e) => ClientError::Io(io::Error::new(e.kind(), e.to_string())),
+    }
+}
@@ -0,0 +1,318 @@
+use crate::transport::Stream;
+use log::warn;
+use rustls::{
+    crypto::ring,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+    server::WebPkiClientVerifier,
+    ClientConnection, Connection, RootCertStore, ServerConnection, StreamOwned,
+};
+use std::{
+    fmt,
+    fs,
+    io::{self, Read, Write},
+    net::TcpStream,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+// Size of the buffer encrypted data is read into before it is decrypted
+const RECORD_BUFFER_SIZE: usize = 16 * 1024;
+
+// Client side of a TLS connection, returned by `ClientTlsConfig::connect`
+pub type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+// Certificate and key a `Server` presents to clients, set with `ServerConfig::tls`.
+// Setting a client CA makes the server require clients to present a certificate signed
+// by it (mutual TLS).
+#[derive(Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    cert_chain_pem: Vec<u8>,        // Server certificate followed by its intermediates
+    private_key_pem: Vec<u8>,       // Key of the server certificate
+    client_ca_pem: Option<Vec<u8>>, // Roots client certificates must chain to, if required
+}
+
+impl TlsConfig {
+    // Creates a configuration from a PEM certificate chain and PEM private key
+    pub fn new(cert_chain_pem: impl Into<Vec<u8>>, private_key_pem: impl Into<Vec<u8>>) -> Self {
+        TlsConfig {
+            cert_chain_pem: cert_chain_pem.into(),
+            private_key_pem: private_key_pem.into(),
+            client_ca_pem: None,
+        }
+    }
+
+    // Creates a configuration from PEM files holding the certificate chain and private key
+    pub fn from_pem_files(cert_chain_path: impl AsRef<Path>, private_key_path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(fs::read(cert_chain_path)?, fs::read(private_key_path)?))
+    }
+
+    // Requires clients to authenticate with a certificate issued by one of the PEM roots
+    pub fn require_client_auth(mut self, client_ca_pem: impl Into<Vec<u8>>) -> Self {
+        self.client_ca_pem = Some(client_ca_pem.into());
+        self
+    }
+
+    // Builds the rustls configuration, failing with `InvalidInput` if the certificates or
+    // key can't be used
+    pub(crate) fn build(&self) -> io::Result<Arc<rustls::ServerConfig>> {
+        let provider = Arc::new(ring::default_provider());
+        let builder = rustls::ServerConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()
+            .map_err(invalid_input)?;
+
+        let builder = match self.client_ca_pem {
+            Some(ref client_ca_pem) => {
+                let roots = Arc::new(root_store(client_ca_pem)?);
+                let verifier = WebPkiClientVerifier::builder_with_provider(roots, provider)
+                    .build()
+                    .map_err(invalid_input)?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let config = builder
+            .with_single_cert(certificates(&self.cert_chain_pem)?, private_key(&self.private_key_pem)?)
+            .map_err(invalid_input)?;
+        Ok(Arc::new(config))
+    }
+}
+
+// Keeps the private key out of logs
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("client_auth", &self.client_ca_pem.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+// Roots a client trusts to identify the server, and optionally the certificate it
+// authenticates itself with to servers requiring mutual TLS
+#[derive(Clone, PartialEq, Eq)]
+pub struct ClientTlsConfig {
+    trust_roots_pem: Vec<u8>,                // Roots the server certificate must chain to
+    identity: Option<(Vec<u8>, Vec<u8>)>,    // PEM certificate chain and private key of the client
+}
+
+impl ClientTlsConfig {
+    // Trusts servers with certificates issued by one of the PEM roots
+    pub fn new(trust_roots_pem: impl Into<Vec<u8>>) -> Self {
+        ClientTlsConfig {
+            trust_roots_pem: trust_roots_pem.into(),
+            identity: None,
+        }
+    }
+
+    // Presents the PEM certificate chain and private key to servers that ask for one
+    pub fn with_identity(mut self, cert_chain_pem: impl Into<Vec<u8>>, private_key_pem: impl Into<Vec<u8>>) -> Self {
+        self.identity = Some((cert_chain_pem.into(), private_key_pem.into()));
+        self
+    }
+
+    // Starts a TLS session over a connected stream. `server_name` is the DNS name or IP
+    // address the server certificate must be valid for. The handshake completes on the
+    // first read or write.
+    pub fn connect(&self, server_name: &str, stream: TcpStream) -> io::Result<TlsStream> {
+        Ok(StreamOwned::new(self.session(server_name)?, stream))
+    }
+
+    // Starts a TLS session over a connected stream, split like the server side of one
+    pub(crate) fn split(&self, server_name: &str, stream: &Stream) -> io::Result<(TlsReader, TlsWriter)> {
+        split_session(stream, Connection::Client(self.session(server_name)?))
+    }
+
+    // Creates the client side of a TLS session with the server named
+    fn session(&self, server_name: &str) -> io::Result<ClientConnection> {
+        let provider = Arc::new(ring::default_provider());
+        let builder = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(invalid_input)?
+            .with_root_certificates(root_store(&self.trust_roots_pem)?);
+
+        let config = match self.identity {
+            Some((ref cert_chain_pem, ref private_key_pem)) => builder
+                .with_client_auth_cert(certificates(cert_chain_pem)?, private_key(private_key_pem)?)
+                .map_err(invalid_input)?,
+            None => builder.with_no_client_auth(),
+        };
+
+        let server_name = ServerName::try_from(server_name.to_string()).map_err(invalid_input)?;
+        ClientConnection::new(Arc::new(config), server_name).map_err(io::Error::other)
+    }
+}
+
+// Keeps the private key out of logs
+impl fmt::Debug for ClientTlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientTlsConfig")
+            .field("identity", &self.identity.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+// Splits the server side of a TLS connection into halves that can be used from different
+// threads: one reading requests while others write responses and pushed messages
+pub(crate) fn split(stream: &Stream, config: &Arc<rustls::ServerConfig>) -> io::Result<(TlsReader, TlsWriter)> {
+    let connection = ServerConnection::new(Arc::clone(config)).map_err(io::Error::other)?;
+    split_session(stream, Connection::Server(connection))
+}
+
+// Splits a TLS session over the stream into reading and writing halves
+fn split_session(stream: &Stream, connection: Connection) -> io::Result<(TlsReader, TlsWriter)> {
+    let session = Arc::new(TlsSession(Mutex::new(connection)));
+    let reader = TlsReader {
+        socket: stream.try_clone()?,
+        session: Arc::clone(&session),
+        received: Vec::new(),
+        eof: false,
+    };
+    let writer = TlsWriter {
+        socket: stream.try_clone()?,
+        session,
+    };
+    Ok((reader, writer))
+}
+
+// TLS state shared by the halves of a connection
+struct TlsSession(Mutex<Connection>);
+
+impl TlsSession {
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Reading half of a TLS connection. Blocks on the socket without holding the session
+// lock, so writers aren't held up by a peer that sends nothing.
+pub(crate) struct TlsReader {
+    socket: Stream,
+    session: Arc<TlsSession>,
+    received: Vec<u8>, // Encrypted data read from the socket but not yet taken by rustls
+    eof: bool,         // Set once the peer has closed its side of the socket
+}
+
+impl Read for TlsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            {
+                let shared = Arc::clone(&self.session);
+                let mut session = shared.lock();
+                self.process_received(&mut session)?;
+                match session.reader().read(buf) {
+                    Ok(n) => return Ok(n), // 0 once the peer has sent close_notify
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    // The peer closed the socket without close_notify
+                    Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(0),
+                    Err(e) => return Err(e),
+                }
+                if !self.received.is_empty() {
+                    // rustls refused data without having any to hand out, which would never resolve
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "TLS record could not be buffered"));
+                }
+                if self.eof {
+                    return Ok(0);
+                }
+            }
+
+            // Wait for more encrypted data; read timeouts surface here as on a plain socket
+            let mut record = [0; RECORD_BUFFER_SIZE];
+            match self.socket.read(&mut record)? {
+                0 => self.eof = true,
+                n => self.received.extend_from_slice(&record[..n]),
+            }
+        }
+    }
+}
+
+impl TlsReader {
+    // Hands the data read from the socket to rustls and sends whatever it wants to reply,
+    // such as handshake messages or alerts. Data is held back while rustls already holds as
+    // much decrypted data as it will buffer, until the caller has taken some of it.
+    fn process_received(&mut self, session: &mut Connection) -> io::Result<()> {
+        let mut received = self.received.as_slice();
+        while !received.is_empty() {
+            if session.read_tls(&mut received).is_err() {
+                break; // Buffers are full
+            }
+            if let Err(e) = session.process_new_packets() {
+                // Tell the peer why the connection is being dropped
+                let _ = write_tls(session, &mut self.socket);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+        }
+        let consumed = self.received.len() - received.len();
+        self.received.drain(..consumed);
+
+        if self.eof && self.received.is_empty() {
+            // Let rustls know no more data is coming
+            session.read_tls(&mut io::empty())?;
+            session.process_new_packets().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+        write_tls(session, &mut self.socket)
+    }
+}
+
+// Writing half of a TLS connection. Data written before the handshake has completed is
+// held by rustls and sent once it has.
+pub(crate) struct TlsWriter {
+    socket: Stream,
+    session: Arc<TlsSession>,
+}
+
+impl Write for TlsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut session = self.session.lock();
+        let written = session.writer().write(buf)?;
+        write_tls(&mut session, &mut self.socket)?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut session = self.session.lock();
+        session.writer().flush()?;
+        write_tls(&mut session, &mut self.socket)
+    }
+}
+
+// Sends every encrypted record rustls has ready
+fn write_tls(session: &mut Connection, socket: &mut Stream) -> io::Result<()> {
+    while session.wants_write() {
+        session.write_tls(socket)?;
+    }
+    Ok(())
+}
+
+// Parses the certificates of a PEM bundle
+fn certificates(pem: &[u8]) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certificates = CertificateDer::pem_slice_iter(pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid_input)?;
+    if certificates.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "No certificate found in PEM data"));
+    }
+    Ok(certificates)
+}
+
+// Parses the first private key of a PEM bundle
+fn private_key(pem: &[u8]) -> io::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_slice(pem).map_err(invalid_input)
+}
+
+// Builds a root store from the certificates of a PEM bundle
+fn root_store(pem: &[u8]) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for certificate in certificates(pem)? {
+        if let Err(e) = roots.add(certificate) {
+            warn!("Ignoring unusable root certificate: {}", e);
+        }
+    }
+    if roots.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "No usable root certificate found"));
+    }
+    Ok(roots)
+}
+
+fn invalid_input(e: impl fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, e.to_string())
+}
@@ -0,0 +1,108 @@
+use crate::config::ServerConfig;
+use crate::framing::encode_frame;
+use crate::handler::{error_response, ConnectionContext, Handler};
+use crate::message::{
+    client_message, server_message, Event, ErrorCode, PublishResponse, ServerMessage, Subscriptions,
+};
+use crate::registry::{Connection, Registry};
+use log::{info, warn};
+use std::sync::Arc;
+
+// Handles Subscribe, Unsubscribe and Publish requests. Subscriptions are kept with each
+// connection in the registry, so they go away with the connection. Their number and the
+// length of topics are limited, as they take up server memory for as long as the
+// connection lasts.
+pub(crate) struct TopicHandler {
+    registry: Arc<Registry>,  // Connections of the server, with their subscriptions
+    max_subscriptions: usize, // Topics a connection may be subscribed to at once
+    max_topic_length: usize,  // Longest topic accepted, in bytes
+}
+
+impl TopicHandler {
+    pub fn new(registry: Arc<Registry>, config: &ServerConfig) -> Self {
+        TopicHandler {
+            registry,
+            max_subscriptions: config.max_subscriptions,
+            max_topic_length: config.max_topic_length,
+        }
+    }
+
+    // Delivers an event to every subscriber of its topic, returning how many it reached
+    fn publish(&self, event: Event) -> u32 {
+        let topic = event.topic.clone();
+        let frame = encode_frame(&ServerMessage {
+            message: Some(server_message::Message::Event(event)),
+            request_id: 0,
+        });
+
+        let mut delivered = 0;
+        for (id, connection) in self.registry.all() {
+            if !connection.subscriptions().contains(&topic) {
+                continue;
+            }
+            match connection.write(&frame) {
+                Ok(()) => delivered += 1,
+                Err(e) => warn!("Error delivering event on {} to connection {}: {}", topic, id, e),
+            }
+        }
+        delivered
+    }
+}
+
+impl Handler for TopicHandler {
+    fn handle(
+        &self,
+        request: &client_message::Message,
+        context: &ConnectionContext,
+    ) -> Option<server_message::Message> {
+        let topic = match request {
+            client_message::Message::Subscribe(subscribe) => &subscribe.topic,
+            client_message::Message::Unsubscribe(unsubscribe) => &unsubscribe.topic,
+            client_message::Message::Publish(publish) => &publish.topic,
+            _ => return None,
+        };
+        if topic.is_empty() {
+            return Some(error_response(ErrorCode::MalformedRequest, "Topic must not be empty".to_string()));
+        }
+        if topic.len() > self.max_topic_length {
+            let detail = format!("Topic is longer than {} bytes", self.max_topic_length);
+            return Some(error_response(ErrorCode::MalformedRequest, detail));
+        }
+
+        // Connections are registered before their first request is read
+        let connection = self.registry.get(context.connection_id)?;
+        Some(match request {
+            client_message::Message::Subscribe(subscribe) => {
+                let mut topics = connection.subscriptions();
+                if !topics.contains(&subscribe.topic) && topics.len() >= self.max_subscriptions {
+                    let detail = format!("Connection is already subscribed to {} topics", self.max_subscriptions);
+                    return Some(error_response(ErrorCode::TooManySubscriptions, detail));
+                }
+                info!("Connection {} subscribed to {}", context.connection_id, subscribe.topic);
+                topics.insert(subscribe.topic.clone());
+                drop(topics);
+                subscriptions(&connection)
+            }
+            client_message::Message::Unsubscribe(unsubscribe) => {
+                info!("Connection {} unsubscribed from {}", context.connection_id, unsubscribe.topic);
+                connection.subscriptions().remove(&unsubscribe.topic);
+                subscriptions(&connection)
+            }
+            client_message::Message::Publish(publish) => {
+                let subscribers = self.publish(Event {
+                    topic: publish.topic.clone(),
+                    payload: publish.payload.clone(),
+                    publisher: context.connection_id,
+                });
+                server_message::Message::PublishResponse(PublishResponse { subscribers })
+            }
+            _ => unreachable!("Request kinds are filtered above"),
+        })
+    }
+}
+
+// Builds the response listing the topics a connection is subscribed to
+fn subscriptions(connection: &Connection) -> server_message::Message {
+    let topics = connection.subscriptions().iter().cloned().collect();
+    server_message::Message::Subscriptions(Subscriptions { topics })
+}
@@ -0,0 +1,317 @@
+use crate::config::ServerConfig;
+use crate::handler::Address;
+use crate::server::bind_listener;
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream},
+    time::Duration,
+};
+#[cfg(unix)]
+use log::{info, warn};
+#[cfg(unix)]
+use std::{
+    fs,
+    os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt},
+    os::unix::net::{self, UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+
+// Half of a connection requests are read from
+pub(crate) type Reader = Box<dyn Read + Send>;
+
+// Half of a connection responses and pushed messages are written to
+pub(crate) type Writer = Box<dyn Write + Send>;
+
+// Runs the same expression on whichever kind of stream `$stream` holds
+macro_rules! with_stream {
+    ($stream:expr, $s:ident => $call:expr) => {
+        match $stream {
+            Stream::Tcp($s) => $call,
+            #[cfg(unix)]
+            Stream::Unix($s) => $call,
+        }
+    };
+}
+
+// A connection, over TCP or a Unix domain socket
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(with_stream!(self, s => s.try_clone()?.into()))
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        with_stream!(self, s => s.shutdown(how))
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        with_stream!(self, s => s.set_nonblocking(nonblocking))
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        with_stream!(self, s => s.set_read_timeout(timeout))
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        with_stream!(self, s => s.set_write_timeout(timeout))
+    }
+
+    // Address of the connected client
+    pub fn peer_addr(&self) -> io::Result<Address> {
+        match self {
+            Stream::Tcp(s) => s.peer_addr().map(Address::Tcp),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.peer_addr().map(|address| unix_address(&address)),
+        }
+    }
+
+    // Address the connection was accepted on
+    pub fn local_addr(&self) -> io::Result<Address> {
+        match self {
+            Stream::Tcp(s) => s.local_addr().map(Address::Tcp),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.local_addr().map(|address| unix_address(&address)),
+        }
+    }
+}
+
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Self {
+        Stream::Tcp(stream)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for Stream {
+    fn from(stream: UnixStream) -> Self {
+        Stream::Unix(stream)
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        with_stream!(self, s => s.read(buf))
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        with_stream!(self, s => s.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        with_stream!(self, s => s.flush())
+    }
+}
+
+// Socket a `Server` accepts connections on
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixSocket),
+}
+
+impl Listener {
+    // Binds the socket described by the configuration: a Unix domain socket if a path is
+    // set, otherwise a TCP listener
+    pub fn bind(config: &ServerConfig) -> io::Result<Self> {
+        #[cfg(unix)]
+        if let Some(ref path) = config.unix_socket {
+            return UnixSocket::bind(path, config.unix_socket_mode).map(Listener::Unix);
+        }
+        bind_listener(config).map(Listener::Tcp)
+    }
+
+    // Waits for a connection, returning it with the address of the client
+    pub fn accept(&self) -> io::Result<(Stream, Address)> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, address)| (stream.into(), address.into())),
+            #[cfg(unix)]
+            Listener::Unix(socket) => {
+                let (stream, address) = socket.listener.accept()?;
+                Ok((stream.into(), unix_address(&address)))
+            }
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Listener::Unix(socket) => socket.listener.set_nonblocking(nonblocking),
+        }
+    }
+
+    // Address the listener is bound to
+    pub fn local_addr(&self) -> io::Result<Address> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(Address::Tcp),
+            #[cfg(unix)]
+            Listener::Unix(socket) => Ok(Address::Unix(Some(socket.path.clone()))),
+        }
+    }
+
+    // Connects to the listener, so a blocked `accept` returns
+    pub fn wake(&self, timeout: Duration) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => {
+                let mut address = listener.local_addr()?;
+                if address.ip().is_unspecified() {
+                    // Wildcard listeners are reachable through the loopback interface
+                    address.set_ip(match address {
+                        SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                        SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+                    });
+                }
+                TcpStream::connect_timeout(&address, timeout).map(drop)
+            }
+            #[cfg(unix)]
+            Listener::Unix(socket) => UnixStream::connect(&socket.path).map(drop),
+        }
+    }
+}
+
+// A listening Unix domain socket. The socket file is removed when it is dropped, unless
+// another server has replaced it in the meantime.
+#[cfg(unix)]
+pub(crate) struct UnixSocket {
+    listener: UnixListener,
+    path: PathBuf, // Where the socket file was created
+    inode: u64,    // Inode of the socket file, to tell it apart from a replacement
+}
+
+#[cfg(unix)]
+impl UnixSocket {
+    // Creates the socket file at `path`, replacing a stale one, and gives it the
+    // permission bits `mode` if set
+    fn bind(path: &Path, mode: Option<u32>) -> io::Result<Self> {
+        remove_stale_socket(path)?;
+        let listener = match mode {
+            Some(mode) => bind_with_mode(path, mode)?,
+            None => UnixListener::bind(path)?,
+        };
+        Ok(UnixSocket {
+            listener,
+            path: path.to_path_buf(),
+            inode: fs::symlink_metadata(path)?.ino(),
+        })
+    }
+}
+
+// Binds a socket that has the permission bits `mode` from the moment it appears at `path`.
+// Binding follows the umask, so the socket is bound in a directory only this process may
+// enter, given its mode there and then linked into place, which fails rather than replace
+// a socket bound at `path` in the meantime.
+#[cfg(unix)]
+fn bind_with_mode(path: &Path, mode: u32) -> io::Result<UnixListener> {
+    // Numbers the directories of servers binding at the same time
+    static BINDS: AtomicUsize = AtomicUsize::new(0);
+
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let bind = BINDS.fetch_add(1, Ordering::Relaxed);
+    let private_dir = parent.join(format!(".socket-{}-{}.tmp", std::process::id(), bind));
+    fs::DirBuilder::new().mode(0o700).create(&private_dir)?;
+
+    let private_path = private_dir.join("s");
+    let result = UnixListener::bind(&private_path).and_then(|listener| {
+        fs::set_permissions(&private_path, fs::Permissions::from_mode(mode))?;
+        fs::hard_link(&private_path, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&private_path);
+    if let Err(e) = fs::remove_dir(&private_dir) {
+        warn!("Error removing {}: {}", private_dir.display(), e);
+    }
+    result
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        let ours = fs::symlink_metadata(&self.path).is_ok_and(|metadata| metadata.ino() == self.inode);
+        if ours {
+            if let Err(e) = fs::remove_file(&self.path) {
+                warn!("Error removing socket {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+// Removes a socket file left behind by a server that didn't exit cleanly. Fails with
+// `AddrInUse` if a server is still listening on it, and with `AlreadyExists` if the path is
+// something other than a socket.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.file_type().is_socket() {
+        let detail = format!("{} exists and is not a socket", path.display());
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, detail));
+    }
+
+    match UnixStream::connect(path) {
+        Ok(_) => {
+            let detail = format!("A server is already listening on {}", path.display());
+            Err(io::Error::new(io::ErrorKind::AddrInUse, detail))
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            info!("Removing stale socket {}", path.display());
+            fs::remove_file(path)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(unix)]
+fn unix_address(address: &net::SocketAddr) -> Address {
+    Address::Unix(address.as_pathname().map(Path::to_path_buf))
+}
+
+// How the bytes of accepted connections are carried
+pub(crate) enum Transport {
+    Plain, // Unencrypted stream
+    #[cfg(feature = "tls")]
+    Tls(Arc<rustls::ServerConfig>), // TLS sessions with the given settings
+}
+
+impl Transport {
+    // Picks the transport described by the server configuration, failing with
+    // `InvalidInput` if it can't be set up
+    pub fn from_config(config: &ServerConfig) -> io::Result<Self> {
+        #[cfg(feature = "tls")]
+        if let Some(ref tls) = config.tls {
+            return Ok(Transport::Tls(tls.build()?));
+        }
+        let _ = config;
+        Ok(Transport::Plain)
+    }
+
+    // Returns true if the server and client exchange raw protocol frames, so a frame can
+    // be written to a connection without setting anything up first
+    pub fn is_plain(&self) -> bool {
+        matches!(self, Transport::Plain)
+    }
+
+    // Splits an accepted stream into halves that can be used from different threads
+    pub fn split(&self, stream: &Stream) -> io::Result<(Reader, Writer)> {
+        match self {
+            Transport::Plain => Ok((Box::new(stream.try_clone()?), Box::new(stream.try_clone()?))),
+            #[cfg(feature = "tls")]
+            Transport::Tls(config) => {
+                let (reader, writer) = crate::tls::split(stream, config)?;
+                Ok((Box::new(reader), Box::new(writer)))
+            }
+        }
+    }
+}
@@ -0,0 +1,165 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use common::expect_error_response;
+use embedded_recruitment_task::{
+    async_server::AsyncServer,
+    client::{Client, Endpoint},
+    config::ServerConfig,
+    message::{client_message, server_message, AddRequest, ClientMessage, EchoMessage, ErrorCode},
+};
+use std::{
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+// Runs the server on a single-threaded tokio runtime in a new thread, so every client is
+// served by a task rather than a thread of its own
+fn setup_server_thread(server: Arc<AsyncServer>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Failed to build tokio runtime");
+        runtime.block_on(server.run()).expect("Server encountered an error");
+    })
+}
+
+// Creates a server instance with the given settings
+fn create_server_with_config(config: ServerConfig) -> Arc<AsyncServer> {
+    let server = AsyncServer::with_config(config).expect("Failed to start server with the given config");
+    Arc::new(server)
+}
+
+// Connects a new client to the server on the given port
+fn connect_client(port: u16) -> Client {
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    client
+}
+
+// Sends a request and returns the payload of the response to it
+fn request(client: &mut Client, message: client_message::Message) -> server_message::Message {
+    let request_id = client.send_request(message).expect("Failed to send message");
+    let response = client.receive_response(request_id).expect("Failed to receive response");
+    assert_eq!(response.request_id, request_id, "Response request id does not match");
+    response.message.expect("Response carries no message")
+}
+
+// Sends an echo request and verifies the content comes back unchanged
+fn expect_echo(client: &mut Client, content: &str) {
+    let message = client_message::Message::EchoMessage(EchoMessage {
+        content: content.to_string(),
+    });
+    match request(client, message) {
+        server_message::Message::EchoMessage(echo) => assert_eq!(echo.content, content),
+        _ => panic!("Expected EchoMessage, but received a different message"),
+    }
+}
+
+// Test: Answer echo and add requests from several clients served concurrently
+#[test]
+fn test_async_echo_and_add() {
+    let server = create_server_with_config(ServerConfig::default());
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut clients: Vec<_> = (0..4).map(|_| connect_client(port)).collect();
+    for (index, client) in clients.iter_mut().enumerate() {
+        expect_echo(client, &format!("Hello from client {}", index));
+        let add = AddRequest { a: 10, b: index as i32, ..Default::default() };
+        match request(client, client_message::Message::AddRequest(add)) {
+            server_message::Message::AddResponse(add) => assert_eq!(add.result, 10 + index as i32),
+            _ => panic!("Expected AddResponse, but received a different message"),
+        }
+    }
+
+    for client in clients.iter_mut() {
+        assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    }
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Answer pipelined requests in order and reject invalid ones like the threaded server
+#[test]
+fn test_async_pipelined_and_invalid_requests() {
+    let server = create_server_with_config(ServerConfig::default().max_message_size(128));
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = connect_client(port);
+    let ids: Vec<u64> = (0..3)
+        .map(|index| {
+            let message = client_message::Message::EchoMessage(EchoMessage {
+                content: format!("pipelined {}", index),
+            });
+            client.send_request(message).expect("Failed to send message")
+        })
+        .collect();
+    for id in ids {
+        assert_eq!(client.receive().expect("Failed to receive response").request_id, id);
+    }
+
+    client.send(ClientMessage::default()).expect("Failed to send message");
+    expect_error_response(&mut client, ErrorCode::EmptyRequest);
+
+    expect_echo(&mut client, "still served");
+
+    let oversized = client_message::Message::EchoMessage(EchoMessage {
+        content: "x".repeat(256),
+    });
+    client.send_request(oversized).expect("Failed to send message");
+    expect_error_response(&mut client, ErrorCode::MessageTooLarge);
+    assert!(client.receive().is_err(), "Server did not close the connection");
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Turn away clients beyond the limit and close idle connections
+#[test]
+fn test_async_connection_limits() {
+    let config = ServerConfig::default().max_clients(1).idle_timeout(Some(Duration::from_millis(300)));
+    let server = create_server_with_config(config);
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut first = connect_client(port);
+    expect_echo(&mut first, "first");
+
+    let mut second = connect_client(port);
+    expect_error_response(&mut second, ErrorCode::ServerBusy);
+    assert!(second.receive().is_err(), "Server did not close the rejected connection");
+
+    // Silence gets the first connection closed, freeing its place
+    thread::sleep(Duration::from_millis(500));
+    assert!(first.receive().is_err(), "Server did not close the idle connection");
+
+    let mut third = connect_client(port);
+    expect_echo(&mut third, "third");
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Stopping the server says goodbye to clients and lets `run` return
+#[test]
+fn test_async_stop_disconnects_clients() {
+    let server = create_server_with_config(ServerConfig::default());
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = connect_client(port);
+    expect_echo(&mut client, "before stop");
+
+    server.stop();
+    handle.join().unwrap();
+    match client.receive().expect("Failed to receive goodbye").message {
+        Some(server_message::Message::Goodbye(_)) => {}
+        _ => panic!("Expected Goodbye, but received a different message"),
+    }
+    assert!(client.receive().is_err(), "Server did not close the connection on stop");
+}
@@ -1,24 +1,30 @@
+mod common;
+
+use common::{create_server_with_config, expect_error_response, setup_server_thread};
 use embedded_recruitment_task::{
-    message::{client_message, server_message, AddRequest, ClientMessage, EchoMessage},
+    auth::{self, AuthMethod},
+    client::{Client, ClientError, Endpoint},
+    config::{ClientConfig, SaturationPolicy, ServerConfig},
+    framing::encode_frame,
+    handler::{ConnectionContext, Handler},
+    message::{
+        auth_request, client_message, server_message, AddRequest, AddResponse, AuthRequest, AuthResponse,
+        ClientMessage, EchoMessage, ErrorCode, Notification, OverflowMode, ServerMessage,
+    },
+    rate_limit::RateLimit,
+    reconnect::ReconnectPolicy,
     server::Server,
+    shared_client::SharedClient,
 };
+use prost::Message;
 use std::{
     io,
-    net::TcpStream,
-    sync::Arc,
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
     thread::{self, JoinHandle},
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
-mod client;
-
-// Spawns a new thread to run the server and returns the thread handle
-fn setup_server_thread(server: Arc<Server>) -> JoinHandle<()> {
-    thread::spawn(move || {
-        server.run().expect("Server encountered an error");
-    })
-}
-
 // Creates and initializes a new server instance
 pub fn create_server() -> Arc<Server> {
     let server = Server::new().expect("Failed to start server");
@@ -29,22 +35,22 @@ pub fn create_server() -> Arc<Server> {
 
 // Sends a message to the server and verifies the response
 fn send_and_receive_message(
-    client: &mut client::Client,
+    client: &mut Client,
     message: embedded_recruitment_task::message::client_message::Message,
     expected_content: Option<impl Into<String>>,
 ) {
-    let client_message = ClientMessage {
-        message: Some(message),
-    };
-
     // Send the message to the server
-    assert!(client.send(client_message).is_ok(), "Failed to send message");
+    let request_id = client.send_request(message);
+    assert!(request_id.is_ok(), "Failed to send message");
+    let request_id = request_id.unwrap();
 
     // Receive and validate the response
-    let response = client.receive();
+    let response = client.receive_response(request_id);
     assert!(response.is_ok(), "Failed to receive response for message");
+    let response = response.unwrap();
+    assert_eq!(response.request_id, request_id, "Response request id does not match");
 
-    match response.unwrap().message {
+    match response.message {
         Some(server_message::Message::EchoMessage(echo)) => {
             if let Some(content) = expected_content {
                 assert_eq!(
@@ -99,7 +105,6 @@ fn wait_for_server(server_port: u16, max_retries: u32) -> bool {
 
 // Finds an available port by binding to an ephemeral port and returning it
 fn find_available_port() -> u16 {
-    use std::net::TcpListener;
     TcpListener::bind("127.0.0.1:0")
         .expect("Failed to bind to find an available port")
         .local_addr()
@@ -122,7 +127,7 @@ fn test_client_connect_disconnect() {
 
     assert!(wait_for_server(port, 20), "Server did not start in time");
 
-    let mut client = client::Client::new("127.0.0.1", port.into(), 5000);
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
     assert!(client.connect().is_ok(), "Failed to connect to server");
     assert!(client.disconnect().is_ok(), "Failed to disconnect from server");
 
@@ -139,11 +144,12 @@ fn test_client_echo_message() {
 
     assert!(wait_for_server(port, 20), "Server did not start in time");
 
-    let mut client = client::Client::new("127.0.0.1", port.into(), 10000);
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
     assert!(client.connect().is_ok(), "Failed to connect to the server");
 
-    let mut echo_message = EchoMessage::default();
-    echo_message.content = "Hello, World!".to_string();
+    let echo_message = EchoMessage {
+        content: "Hello, World!".to_string(),
+    };
     let message = client_message::Message::EchoMessage(echo_message.clone());
 
     send_and_receive_message(
@@ -166,7 +172,7 @@ fn test_multiple_echo_messages() {
 
     assert!(wait_for_server(port, 20), "Server did not start in time");
 
-    let mut client = client::Client::new("127.0.0.1", port.into(), 10000);
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
     assert!(client.connect().is_ok(), "Failed to connect to the server");
 
     let messages = vec![
@@ -176,8 +182,9 @@ fn test_multiple_echo_messages() {
     ];
 
     for message_content in messages {
-        let mut echo_message = EchoMessage::default();
-        echo_message.content = message_content.clone();
+        let echo_message = EchoMessage {
+            content: message_content.clone(),
+        };
         let message = client_message::Message::EchoMessage(echo_message);
 
         send_and_receive_message(&mut client, message, Some(message_content.clone()));
@@ -200,10 +207,10 @@ fn test_multiple_clients() {
     println!("Starting server on port {}", port);
     assert!(wait_for_server(port, 20), "Server did not start in time");
 
-    let mut clients = vec![
-        client::Client::new("127.0.0.1", port.into(), 10000),
-        client::Client::new("127.0.0.1", port.into(), 10000),
-        client::Client::new("127.0.0.1", port.into(), 10000),
+    let mut clients = [
+        Client::new(Endpoint::tcp("127.0.0.1", port)),
+        Client::new(Endpoint::tcp("127.0.0.1", port)),
+        Client::new(Endpoint::tcp("127.0.0.1", port)),
     ];
 
     println!("Connecting clients...");
@@ -212,15 +219,16 @@ fn test_multiple_clients() {
         println!("Client {} connected", index + 1);
     }
 
-    let messages = vec![
+    let messages = [
         "Hello, World!".to_string(),
         "How are you?".to_string(),
         "Goodbye!".to_string(),
     ];
 
     for (msg_index, message_content) in messages.iter().enumerate() {
-        let mut echo_message = EchoMessage::default();
-        echo_message.content = message_content.clone();
+        let echo_message = EchoMessage {
+            content: message_content.clone(),
+        };
         let message = client_message::Message::EchoMessage(echo_message.clone());
 
         println!("Broadcasting message {} to all clients: {:?}", msg_index + 1, message_content);
@@ -254,12 +262,13 @@ fn test_client_add_request() {
 
     assert!(wait_for_server(port, 20), "Server did not start in time");
 
-    let mut client = client::Client::new("127.0.0.1", port.into(), 10000);
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
     assert!(client.connect().is_ok(), "Failed to connect to the server");
 
-    let add_request = AddRequest { a: 10, b: 20 };
+    let add_request = AddRequest { a: 10, b: 20, ..Default::default() };
     let message = ClientMessage {
-        message: Some(client_message::Message::AddRequest(add_request.clone())),
+        message: Some(client_message::Message::AddRequest(add_request)),
+        ..Default::default()
     };
 
     println!("Sending AddRequest: {:?}", add_request);
@@ -285,3 +294,1683 @@ fn test_client_add_request() {
     server.stop();
     handle.join().unwrap();
 }
+
+// Test: Echo a message larger than a single read buffer
+#[test]
+fn test_large_echo_message() {
+    let port = find_available_port();
+    let server = create_server_with_port(port);
+    let handle = setup_server_thread(server.clone());
+
+    assert!(wait_for_server(port, 20), "Server did not start in time");
+
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let content = "x".repeat(32 * 1024);
+    let message = client_message::Message::EchoMessage(EchoMessage {
+        content: content.clone(),
+    });
+    send_and_receive_message(&mut client, message, Some(content));
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Pipeline several requests before reading any response
+#[test]
+fn test_pipelined_messages() {
+    let port = find_available_port();
+    let server = create_server_with_port(port);
+    let handle = setup_server_thread(server.clone());
+
+    assert!(wait_for_server(port, 20), "Server did not start in time");
+
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // Coalesce all requests into a single write so they reach the server in one read
+    let contents: Vec<String> = (0..10).map(|i| format!("Message {}", i)).collect();
+    let mut batch = Vec::new();
+    for content in &contents {
+        let message = ClientMessage {
+            message: Some(client_message::Message::EchoMessage(EchoMessage {
+                content: content.clone(),
+            })),
+            ..Default::default()
+        };
+        batch.extend(encode_frame(&message));
+    }
+    assert!(client.send_raw(&batch).is_ok(), "Failed to send pipelined messages");
+
+    for content in contents {
+        match client.receive().expect("Failed to receive response").message {
+            Some(server_message::Message::EchoMessage(echo)) => {
+                assert_eq!(echo.content, content, "Responses arrived out of order");
+            }
+            _ => panic!("Expected EchoMessage, but received a different message"),
+        }
+    }
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Reassemble a message that arrives split across several writes
+#[test]
+fn test_split_message() {
+    let port = find_available_port();
+    let server = create_server_with_port(port);
+    let handle = setup_server_thread(server.clone());
+
+    assert!(wait_for_server(port, 20), "Server did not start in time");
+
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let message = ClientMessage {
+        message: Some(client_message::Message::AddRequest(AddRequest { a: 3, b: 4, ..Default::default() })),
+        ..Default::default()
+    };
+    for byte in encode_frame(&message) {
+        assert!(client.send_raw(&[byte]).is_ok(), "Failed to send partial frame");
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    match client.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::AddResponse(add_response)) => {
+            assert_eq!(add_response.result, 7, "AddResponse result does not match");
+        }
+        _ => panic!("Expected AddResponse, but received a different message"),
+    }
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Reject a message larger than the configured maximum and close the connection
+#[test]
+fn test_oversized_message_rejected() {
+    let port = find_available_port();
+    let server = create_server_with_config(ServerConfig::default().port(port).max_message_size(128));
+    let handle = setup_server_thread(server.clone());
+
+    assert!(wait_for_server(port, 20), "Server did not start in time");
+
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let message = ClientMessage {
+        message: Some(client_message::Message::EchoMessage(EchoMessage {
+            content: "x".repeat(512),
+        })),
+        ..Default::default()
+    };
+    assert!(client.send(message).is_ok(), "Failed to send message");
+
+    expect_error_response(&mut client, ErrorCode::MessageTooLarge);
+    assert!(client.receive().is_err(), "Server did not close the connection");
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Reject a frame header announcing a huge payload without waiting for the payload
+#[test]
+fn test_oversized_frame_header_rejected() {
+    let port = find_available_port();
+    let server = create_server_with_port(port);
+    let handle = setup_server_thread(server.clone());
+
+    assert!(wait_for_server(port, 20), "Server did not start in time");
+
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // Varint length prefix announcing a 1 GiB payload, followed by only a few bytes
+    assert!(client.send_raw(&[0x80, 0x80, 0x80, 0x80, 0x04, 0x0a, 0x00]).is_ok(), "Failed to send frame header");
+
+    expect_error_response(&mut client, ErrorCode::MessageTooLarge);
+    assert!(client.receive().is_err(), "Server did not close the connection");
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Answer malformed, empty and unsupported requests with an error instead of silence
+#[test]
+fn test_invalid_requests_answered_with_error() {
+    let port = find_available_port();
+    let server = create_server_with_port(port);
+    let handle = setup_server_thread(server.clone());
+
+    assert!(wait_for_server(port, 20), "Server did not start in time");
+
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // A frame whose payload is not a valid protobuf message
+    assert!(client.send_raw(&[0x02, 0xff, 0xff]).is_ok(), "Failed to send malformed frame");
+    expect_error_response(&mut client, ErrorCode::MalformedRequest);
+
+    // A ClientMessage without any payload
+    assert!(client.send(ClientMessage::default()).is_ok(), "Failed to send empty message");
+    expect_error_response(&mut client, ErrorCode::EmptyRequest);
+
+    // A ClientMessage carrying a oneof arm (field 9) the server doesn't know about
+    assert!(client.send_raw(&[0x02, 0x48, 0x01]).is_ok(), "Failed to send unsupported message");
+    expect_error_response(&mut client, ErrorCode::UnsupportedRequest);
+
+    // The connection stays usable after an error response
+    let message = client_message::Message::EchoMessage(EchoMessage {
+        content: "Still here".to_string(),
+    });
+    send_and_receive_message(&mut client, message, Some("Still here"));
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Correlate pipelined responses with their requests by request id
+#[test]
+fn test_request_id_correlation() {
+    let port = find_available_port();
+    let server = create_server_with_port(port);
+    let handle = setup_server_thread(server.clone());
+
+    assert!(wait_for_server(port, 20), "Server did not start in time");
+
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let echo_id = client
+        .send_request(client_message::Message::EchoMessage(EchoMessage {
+            content: "first".to_string(),
+        }))
+        .expect("Failed to send EchoMessage");
+    let add_id = client
+        .send_request(client_message::Message::AddRequest(AddRequest { a: 1, b: 2, ..Default::default() }))
+        .expect("Failed to send AddRequest");
+    assert!(
+        client
+            .send(ClientMessage {
+                message: None,
+                request_id: 42,
+            })
+            .is_ok(),
+        "Failed to send empty message"
+    );
+
+    // Await the responses in the reverse order of the requests
+    let error = client.receive_response(42).expect("Failed to receive error response");
+    assert!(
+        matches!(error.message, Some(server_message::Message::ErrorResponse(_))),
+        "Expected ErrorResponse for request 42"
+    );
+
+    let add = client.receive_response(add_id).expect("Failed to receive AddResponse");
+    assert_eq!(add.request_id, add_id, "Response request id does not match");
+    match add.message {
+        Some(server_message::Message::AddResponse(add_response)) => assert_eq!(add_response.result, 3),
+        _ => panic!("Expected AddResponse, but received a different message"),
+    }
+
+    let echo = client.receive_response(echo_id).expect("Failed to receive EchoMessage");
+    assert_eq!(echo.request_id, echo_id, "Response request id does not match");
+    match echo.message {
+        Some(server_message::Message::EchoMessage(echo)) => assert_eq!(echo.content, "first"),
+        _ => panic!("Expected EchoMessage, but received a different message"),
+    }
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Detect AddRequest overflow in checked mode and honour saturating and wrapping modes
+#[test]
+fn test_add_request_overflow() {
+    let port = find_available_port();
+    let server = create_server_with_port(port);
+    let handle = setup_server_thread(server.clone());
+
+    assert!(wait_for_server(port, 20), "Server did not start in time");
+
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    let add = |a: i32, b: i32, mode: OverflowMode| {
+        client_message::Message::AddRequest(AddRequest { a, b, mode: mode.into() })
+    };
+
+    // Checked mode: boundaries that fit still succeed, overflow in either direction is an error
+    send_and_receive_message(&mut client, add(i32::MAX, 0, OverflowMode::Checked), Some(i32::MAX.to_string()));
+    send_and_receive_message(&mut client, add(i32::MAX, i32::MIN, OverflowMode::Checked), Some("-1"));
+    assert!(client.send_request(add(i32::MAX, 1, OverflowMode::Checked)).is_ok(), "Failed to send message");
+    expect_error_response(&mut client, ErrorCode::ArithmeticOverflow);
+    assert!(client.send_request(add(i32::MIN, -1, OverflowMode::Checked)).is_ok(), "Failed to send message");
+    expect_error_response(&mut client, ErrorCode::ArithmeticOverflow);
+
+    // Saturating mode clamps to the int32 range
+    send_and_receive_message(&mut client, add(i32::MAX, 1, OverflowMode::Saturating), Some(i32::MAX.to_string()));
+    send_and_receive_message(&mut client, add(i32::MIN, -1, OverflowMode::Saturating), Some(i32::MIN.to_string()));
+
+    // Wrapping mode wraps around
+    send_and_receive_message(&mut client, add(i32::MAX, 1, OverflowMode::Wrapping), Some(i32::MIN.to_string()));
+    send_and_receive_message(&mut client, add(i32::MIN, -1, OverflowMode::Wrapping), Some(i32::MAX.to_string()));
+
+    // The connection survives an overflow
+    send_and_receive_message(&mut client, add(10, 20, OverflowMode::Checked), Some("30"));
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Application handler multiplying the operands of an AddRequest sent as an application message
+struct MultiplyHandler;
+
+impl MultiplyHandler {
+    const REQUEST_TYPE: &'static str = "example/Multiply";
+    const RESPONSE_TYPE: &'static str = "example/Product";
+}
+
+impl Handler for MultiplyHandler {
+    fn handle(
+        &self,
+        request: &client_message::Message,
+        _context: &ConnectionContext,
+    ) -> Option<server_message::Message> {
+        match request {
+            client_message::Message::ApplicationMessage(any) if any.type_url == Self::REQUEST_TYPE => {
+                let operands = AddRequest::decode(any.value.as_slice()).ok()?;
+                let product = AddResponse {
+                    result: operands.a.wrapping_mul(operands.b),
+                };
+                Some(server_message::Message::ApplicationMessage(prost_types::Any {
+                    type_url: Self::RESPONSE_TYPE.to_string(),
+                    value: product.encode_to_vec(),
+                }))
+            }
+            _ => None,
+        }
+    }
+}
+
+// Application handler taking over EchoMessage to answer with the client's address
+struct PeerEchoHandler;
+
+impl Handler for PeerEchoHandler {
+    fn handle(
+        &self,
+        request: &client_message::Message,
+        context: &ConnectionContext,
+    ) -> Option<server_message::Message> {
+        match request {
+            client_message::Message::EchoMessage(_) => Some(server_message::Message::EchoMessage(EchoMessage {
+                content: context.peer_addr.to_string(),
+            })),
+            _ => None,
+        }
+    }
+}
+
+// Handler delaying every request before passing it on, to keep requests in flight
+struct SlowHandler(Duration);
+
+impl Handler for SlowHandler {
+    fn handle(
+        &self,
+        _request: &client_message::Message,
+        _context: &ConnectionContext,
+    ) -> Option<server_message::Message> {
+        thread::sleep(self.0);
+        None
+    }
+}
+
+// Test: Dispatch application messages and overridden built-ins to custom handlers
+#[test]
+fn test_custom_handlers() {
+    let port = find_available_port();
+    let server = Arc::new(
+        Server::new_with_port(port)
+            .expect("Failed to start server on the specified port")
+            .with_handler(MultiplyHandler)
+            .with_handler(PeerEchoHandler),
+    );
+    let handle = setup_server_thread(server.clone());
+
+    assert!(wait_for_server(port, 20), "Server did not start in time");
+
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // The custom handler answers its own application message type
+    let operands = AddRequest { a: 6, b: 7, ..Default::default() };
+    let request_id = client
+        .send_request(client_message::Message::ApplicationMessage(prost_types::Any {
+            type_url: MultiplyHandler::REQUEST_TYPE.to_string(),
+            value: operands.encode_to_vec(),
+        }))
+        .expect("Failed to send application message");
+    match client.receive_response(request_id).expect("Failed to receive response").message {
+        Some(server_message::Message::ApplicationMessage(any)) => {
+            assert_eq!(any.type_url, MultiplyHandler::RESPONSE_TYPE, "Unexpected response type");
+            let product = AddResponse::decode(any.value.as_slice()).expect("Failed to decode product");
+            assert_eq!(product.result, 42, "Product does not match");
+        }
+        _ => panic!("Expected ApplicationMessage, but received a different message"),
+    }
+
+    // Application messages no handler recognises are unsupported
+    assert!(
+        client
+            .send_request(client_message::Message::ApplicationMessage(prost_types::Any {
+                type_url: "example/Unknown".to_string(),
+                value: Vec::new(),
+            }))
+            .is_ok(),
+        "Failed to send application message"
+    );
+    expect_error_response(&mut client, ErrorCode::UnsupportedRequest);
+
+    // A custom handler takes precedence over the built-in EchoMessage handler
+    let message = client_message::Message::EchoMessage(EchoMessage {
+        content: "Who am I?".to_string(),
+    });
+    let local_addr = client.local_addr().expect("Failed to retrieve local address");
+    send_and_receive_message(&mut client, message, Some(local_addr.to_string()));
+
+    // Requests no custom handler accepts still reach the built-in handlers
+    send_and_receive_message(&mut client, client_message::Message::AddRequest(operands), Some("13"));
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Serve IPv4 and IPv6 clients from a single dual-stack listener
+#[test]
+fn test_dual_stack_listener() {
+    let server = create_server_with_config(ServerConfig::default().any_address());
+    let port = server.get_port().expect("Failed to retrieve server port");
+    assert!(server.local_addr().unwrap().is_ipv6(), "Server is not listening on IPv6");
+    let handle = setup_server_thread(server.clone());
+
+    for ip in ["127.0.0.1", "::1"] {
+        let mut client = Client::new(Endpoint::tcp(ip, port));
+        assert!(client.connect().is_ok(), "Failed to connect to the server over {}", ip);
+
+        let message = client_message::Message::EchoMessage(EchoMessage {
+            content: ip.to_string(),
+        });
+        send_and_receive_message(&mut client, message, Some(ip));
+        assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    }
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Turn away clients beyond the configured maximum
+#[test]
+fn test_max_clients() {
+    // The listener is bound on creation, so clients can connect before the server runs
+    let server = create_server_with_config(ServerConfig::default().max_clients(1));
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut first = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(first.connect().is_ok(), "Failed to connect to the server");
+    let message = client_message::Message::EchoMessage(EchoMessage {
+        content: "first".to_string(),
+    });
+    send_and_receive_message(&mut first, message.clone(), Some("first"));
+
+    let mut second = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(second.connect().is_ok(), "Failed to connect to the server");
+    expect_error_response(&mut second, ErrorCode::ServerBusy);
+    assert!(second.receive().is_err(), "Server did not close the rejected connection");
+
+    // The first client is unaffected
+    send_and_receive_message(&mut first, message, Some("first"));
+
+    assert!(first.disconnect().is_ok(), "Failed to disconnect from the server");
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Close connections that stay silent for longer than the idle timeout
+#[test]
+fn test_idle_timeout() {
+    let server = create_server_with_config(ServerConfig::default().idle_timeout(Some(Duration::from_millis(200))));
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // Activity keeps the connection open past the idle timeout
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        let message = client_message::Message::EchoMessage(EchoMessage {
+            content: "still here".to_string(),
+        });
+        send_and_receive_message(&mut client, message, Some("still here"));
+    }
+
+    // Silence gets the connection closed
+    let started = std::time::Instant::now();
+    assert!(client.receive().is_err(), "Server did not close the idle connection");
+    assert!(started.elapsed() < Duration::from_secs(5), "Idle connection closed too late");
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Close connections that start a message but never finish it
+#[test]
+fn test_read_timeout() {
+    let server = create_server_with_config(ServerConfig::default().read_timeout(Some(Duration::from_millis(200))));
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // Announce a 16 byte message but only send part of it
+    assert!(client.send_raw(&[0x10, 0x0a, 0x02]).is_ok(), "Failed to send partial frame");
+
+    let started = std::time::Instant::now();
+    assert!(client.receive().is_err(), "Server did not close the stalled connection");
+    assert!(started.elapsed() < Duration::from_secs(5), "Stalled connection closed too late");
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Accept connections, answer requests and stop without polling delays. Each step is
+// repeated and timed as a whole against what polling every 100 ms would have added at most,
+// leaving a wide margin for a loaded machine.
+#[test]
+fn test_connection_latency() {
+    let poll_interval = Duration::from_millis(100);
+    let server = create_server();
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    // Each round trip used to wait for up to one poll interval before the accept loop polled again
+    let rounds = 20;
+    let started = std::time::Instant::now();
+    for round in 0..rounds {
+        let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+        assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+        let content = format!("round {}", round);
+        let message = client_message::Message::EchoMessage(EchoMessage {
+            content: content.clone(),
+        });
+        send_and_receive_message(&mut client, message, Some(content));
+        assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    }
+    let elapsed = started.elapsed();
+    println!("{} connect and echo round trips took {:?}", rounds, elapsed);
+    assert!(elapsed < poll_interval * rounds, "Round trips took {:?}", elapsed);
+
+    // A response after an idle period is just as quick
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let idle_rounds = 5;
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..idle_rounds {
+        thread::sleep(poll_interval + Duration::from_millis(50));
+        let started = std::time::Instant::now();
+        let message = client_message::Message::EchoMessage(EchoMessage {
+            content: "after idle".to_string(),
+        });
+        send_and_receive_message(&mut client, message, Some("after idle"));
+        elapsed += started.elapsed();
+    }
+    assert!(elapsed < poll_interval * idle_rounds, "Responses after idle periods took {:?}", elapsed);
+
+    // Stopping wakes the blocked accept loop straight away
+    let started = std::time::Instant::now();
+    server.stop();
+    handle.join().unwrap();
+    let mut elapsed = started.elapsed();
+    let stop_rounds = 5;
+    for _ in 1..stop_rounds {
+        let server = create_server();
+        let handle = setup_server_thread(server.clone());
+        // A ping is only answered once the server is running
+        let mut client = Client::new(Endpoint::tcp("127.0.0.1", server.get_port().unwrap()));
+        assert!(client.connect().is_ok(), "Failed to connect to the server");
+        assert!(client.ping().is_ok(), "Ping was not answered");
+        assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+        let started = std::time::Instant::now();
+        server.stop();
+        handle.join().unwrap();
+        elapsed += started.elapsed();
+    }
+    assert!(elapsed < poll_interval * stop_rounds, "Stops took {:?}", elapsed);
+}
+
+// Test: Reject clients with an error frame while every worker is busy
+#[test]
+fn test_worker_pool_saturation_rejects() {
+    let config = ServerConfig::default()
+        .worker_threads(1)
+        .accept_queue_size(0)
+        .saturation_policy(SaturationPolicy::Reject);
+    let server = create_server_with_config(config);
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let message = client_message::Message::EchoMessage(EchoMessage {
+        content: "busy".to_string(),
+    });
+    let mut first = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(first.connect().is_ok(), "Failed to connect to the server");
+    send_and_receive_message(&mut first, message.clone(), Some("busy"));
+
+    // The only worker is serving the first client
+    let mut second = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(second.connect().is_ok(), "Failed to connect to the server");
+    expect_error_response(&mut second, ErrorCode::ServerBusy);
+    assert!(second.receive().is_err(), "Server did not close the rejected connection");
+
+    // Once the worker is free again new clients are served
+    assert!(first.disconnect().is_ok(), "Failed to disconnect from the server");
+    let mut served = false;
+    for _ in 0..50 {
+        let mut third = Client::new(Endpoint::tcp("127.0.0.1", port));
+        assert!(third.connect().is_ok(), "Failed to connect to the server");
+        let request_id = third.send_request(message.clone()).expect("Failed to send message");
+        match third.receive_response(request_id) {
+            Ok(response) if matches!(response.message, Some(server_message::Message::EchoMessage(_))) => {
+                served = true;
+                break;
+            }
+            _ => thread::sleep(Duration::from_millis(20)),
+        }
+    }
+    assert!(served, "Client was not served after the worker freed up");
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Hold clients back until a worker is free when configured to wait
+#[test]
+fn test_worker_pool_saturation_waits() {
+    let config = ServerConfig::default()
+        .worker_threads(1)
+        .accept_queue_size(0)
+        .saturation_policy(SaturationPolicy::Wait);
+    let server = create_server_with_config(config);
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut first = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(first.connect().is_ok(), "Failed to connect to the server");
+    let message = client_message::Message::EchoMessage(EchoMessage {
+        content: "first".to_string(),
+    });
+    send_and_receive_message(&mut first, message, Some("first"));
+
+    // The second client connects through the OS backlog but isn't served yet
+    let mut second = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(second.connect().is_ok(), "Failed to connect to the server");
+    let request_id = second
+        .send_request(client_message::Message::EchoMessage(EchoMessage {
+            content: "second".to_string(),
+        }))
+        .expect("Failed to send message");
+    second.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+    assert!(second.receive_response(request_id).is_err(), "Queued client was served early");
+
+    // Freeing the worker lets the queued client through
+    assert!(first.disconnect().is_ok(), "Failed to disconnect from the server");
+    second.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    match second.receive_response(request_id).expect("Queued client was not served").message {
+        Some(server_message::Message::EchoMessage(echo)) => assert_eq!(echo.content, "second"),
+        _ => panic!("Expected EchoMessage, but received a different message"),
+    }
+
+    assert!(second.disconnect().is_ok(), "Failed to disconnect from the server");
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Receives the next response and verifies it is a busy error saying why the client was turned away
+fn expect_busy(client: &mut Client, reason: &str) {
+    match client.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::ErrorResponse(error)) => {
+            assert_eq!(error.code(), ErrorCode::ServerBusy, "Unexpected error code: {}", error.detail);
+            assert!(error.detail.contains(reason), "Unexpected rejection: {}", error.detail);
+        }
+        _ => panic!("Expected ErrorResponse, but received a different message"),
+    }
+}
+
+// Test: Stop counting clients the saturated worker pool turned away, so they don't use up
+// the maximum number of clients
+#[test]
+fn test_worker_pool_saturation_with_max_clients() {
+    let config = ServerConfig::default()
+        .worker_threads(1)
+        .accept_queue_size(0)
+        .saturation_policy(SaturationPolicy::Reject)
+        .max_clients(2);
+    let server = create_server_with_config(config);
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let message = client_message::Message::EchoMessage(EchoMessage {
+        content: "busy".to_string(),
+    });
+    let mut first = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(first.connect().is_ok(), "Failed to connect to the server");
+    send_and_receive_message(&mut first, message.clone(), Some("busy"));
+
+    // Turned away for lack of a worker, not for reaching the maximum, each time
+    for _ in 0..3 {
+        let mut rejected = Client::new(Endpoint::tcp("127.0.0.1", port));
+        assert!(rejected.connect().is_ok(), "Failed to connect to the server");
+        expect_busy(&mut rejected, "workers");
+    }
+
+    // Once the worker is free again a new client is served, and the one after it is again
+    // only turned away because the worker is busy
+    assert!(first.disconnect().is_ok(), "Failed to disconnect from the server");
+    let mut served = None;
+    for _ in 0..50 {
+        let mut third = Client::new(Endpoint::tcp("127.0.0.1", port));
+        assert!(third.connect().is_ok(), "Failed to connect to the server");
+        let request_id = third.send_request(message.clone()).expect("Failed to send message");
+        match third.receive_response(request_id) {
+            Ok(response) if matches!(response.message, Some(server_message::Message::EchoMessage(_))) => {
+                served = Some(third);
+                break;
+            }
+            _ => thread::sleep(Duration::from_millis(20)),
+        }
+    }
+    assert!(served.is_some(), "Client was not served after the worker freed up");
+    let mut fourth = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(fourth.connect().is_ok(), "Failed to connect to the server");
+    expect_busy(&mut fourth, "workers");
+
+    assert_eq!(server.stop().connections_closed, 1, "Rejected clients were counted as served");
+    handle.join().unwrap();
+}
+
+// Receives the next message and verifies it is the goodbye sent by a stopping server
+fn expect_goodbye(client: &mut Client) {
+    match client.receive().expect("Failed to receive goodbye").message {
+        Some(server_message::Message::Goodbye(_)) => {}
+        _ => panic!("Expected Goodbye, but received a different message"),
+    }
+}
+
+// Test: Answer in-flight requests and say goodbye before a graceful stop completes
+#[test]
+fn test_graceful_stop() {
+    let server = Arc::new(
+        Server::with_config(ServerConfig::default())
+            .expect("Failed to start server with the given config")
+            .with_handler(SlowHandler(Duration::from_millis(300))),
+    );
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut busy = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(busy.connect().is_ok(), "Failed to connect to the server");
+    let mut idle = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(idle.connect().is_ok(), "Failed to connect to the server");
+    send_and_receive_message(&mut idle, client_message::Message::EchoMessage(EchoMessage::default()), Some(""));
+
+    // Stop while the first request is being handled and the second one, sent meanwhile,
+    // waits to be read
+    let request_ids: Vec<u64> = ["in flight", "waiting"]
+        .into_iter()
+        .map(|content| {
+            let request_id = busy
+                .send_request(client_message::Message::EchoMessage(EchoMessage {
+                    content: content.to_string(),
+                }))
+                .expect("Failed to send message");
+            thread::sleep(Duration::from_millis(50));
+            request_id
+        })
+        .collect();
+    let summary = server.stop();
+    assert_eq!(summary.connections_closed, 2);
+    assert_eq!(summary.connections_forced, 0);
+
+    // `stop` returned after every worker finished, so `run` has returned too
+    assert!(handle.is_finished(), "Server is still running after stop returned");
+    handle.join().unwrap();
+
+    for (request_id, content) in request_ids.into_iter().zip(["in flight", "waiting"]) {
+        match busy.receive_response(request_id).expect("Request sent before stop was not answered").message {
+            Some(server_message::Message::EchoMessage(echo)) => assert_eq!(echo.content, content),
+            _ => panic!("Expected EchoMessage, but received a different message"),
+        }
+    }
+    for client in [&mut busy, &mut idle] {
+        expect_goodbye(client);
+        assert!(client.receive().is_err(), "Server did not close the connection");
+    }
+}
+
+// Test: Cut off requests still running when the shutdown timeout expires
+#[test]
+fn test_shutdown_timeout() {
+    let server = Arc::new(
+        Server::with_config(ServerConfig::default().shutdown_timeout(Duration::from_millis(100)))
+            .expect("Failed to start server with the given config")
+            .with_handler(SlowHandler(Duration::from_millis(1000))),
+    );
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    client
+        .send_request(client_message::Message::EchoMessage(EchoMessage::default()))
+        .expect("Failed to send message");
+    thread::sleep(Duration::from_millis(100));
+
+    let summary = server.stop();
+    assert_eq!(summary.connections_closed, 1);
+    assert_eq!(summary.connections_forced, 1);
+    handle.join().unwrap();
+
+    assert!(client.receive().is_err(), "Server answered a request after the shutdown timeout");
+}
+
+// Test: Disconnect clients at once without a goodbye when stopping forcibly
+#[test]
+fn test_force_stop() {
+    let server = create_server_with_config(ServerConfig::default());
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let message = client_message::Message::EchoMessage(EchoMessage {
+        content: "before stop".to_string(),
+    });
+    send_and_receive_message(&mut client, message, Some("before stop"));
+
+    server.force_stop();
+    handle.join().unwrap();
+    assert!(client.receive().is_err(), "Server did not close the connection");
+
+    // A graceful stop after the server has stopped reports what happened
+    assert_eq!(server.stop().connections_forced, 1);
+}
+
+// Waits until the server reports the expected number of connections
+fn wait_for_connection_count(server: &Server, expected: usize) -> bool {
+    for _ in 0..50 {
+        if server.connection_count() == expected {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    false
+}
+
+// Test: Drop connections from the registry once their clients disconnect
+#[test]
+fn test_disconnected_clients_are_pruned() {
+    let server = create_server_with_config(ServerConfig::default());
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+    assert_eq!(server.connection_count(), 0);
+
+    let mut clients: Vec<_> = (0..3)
+        .map(|index| {
+            let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+            assert!(client.connect().is_ok(), "Failed to connect to the server");
+            let content = format!("client {}", index);
+            let message = client_message::Message::EchoMessage(EchoMessage {
+                content: content.clone(),
+            });
+            send_and_receive_message(&mut client, message, Some(content));
+            client
+        })
+        .collect();
+    assert!(wait_for_connection_count(&server, 3), "Server did not count the connected clients");
+
+    for client in clients.iter_mut().skip(1) {
+        assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    }
+    assert!(wait_for_connection_count(&server, 1), "Server kept disconnected clients");
+
+    // Repeated connections don't accumulate
+    for _ in 0..10 {
+        let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+        assert!(client.connect().is_ok(), "Failed to connect to the server");
+        send_and_receive_message(&mut client, client_message::Message::EchoMessage(EchoMessage::default()), Some(""));
+        assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    }
+    assert!(wait_for_connection_count(&server, 1), "Server kept disconnected clients");
+
+    assert_eq!(server.stop().connections_closed, 1);
+    handle.join().unwrap();
+    assert_eq!(server.connection_count(), 0);
+}
+
+// Test: List connections with their activity and disconnect one of them by id
+#[test]
+fn test_connection_registry() {
+    let server = create_server_with_config(ServerConfig::default());
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+    let started = SystemTime::now();
+
+    let mut first = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(first.connect().is_ok(), "Failed to connect to the server");
+    let message = client_message::Message::EchoMessage(EchoMessage {
+        content: "first".to_string(),
+    });
+    send_and_receive_message(&mut first, message, Some("first"));
+    first.send(ClientMessage::default()).expect("Failed to send message");
+    expect_error_response(&mut first, ErrorCode::EmptyRequest);
+
+    let mut second = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(second.connect().is_ok(), "Failed to connect to the server");
+    send_and_receive_message(&mut second, client_message::Message::EchoMessage(EchoMessage::default()), Some(""));
+
+    // Counters are updated once a response has been written, which may be just after the
+    // client read it
+    let mut connections = server.connections();
+    for _ in 0..50 {
+        if connections.iter().map(|info| info.requests).eq([2, 1]) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+        connections = server.connections();
+    }
+    assert_eq!(connections.len(), 2);
+    let (info, other) = (&connections[0], &connections[1]);
+    assert!(info.id < other.id, "Connections are not listed by id");
+    assert_eq!(info.peer_addr, first.local_addr().unwrap());
+    assert_eq!(other.peer_addr, second.local_addr().unwrap());
+    assert_eq!(info.requests, 2);
+    assert_eq!(info.failed_requests, 1);
+    assert_eq!(other.requests, 1);
+    assert_eq!(other.failed_requests, 0);
+    assert!(info.bytes_received > 0 && info.bytes_sent > 0, "Traffic was not counted: {:?}", info);
+    assert!(info.connected_at >= started - Duration::from_secs(1), "Implausible connect time: {:?}", info);
+    assert!(info.last_activity >= info.connected_at, "Implausible activity time: {:?}", info);
+
+    // Kick the first client; the second one is unaffected
+    assert!(server.disconnect(info.id), "Connection to disconnect was not found");
+    assert!(first.receive().is_err(), "Server did not close the connection");
+    assert!(wait_for_connection_count(&server, 1), "Server kept the disconnected client");
+    assert_eq!(server.connections()[0].id, other.id);
+    assert!(!server.disconnect(info.id), "Disconnected a connection twice");
+    send_and_receive_message(&mut second, client_message::Message::EchoMessage(EchoMessage::default()), Some(""));
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Answer pings, letting a quiet client stay connected past the idle timeout
+#[test]
+fn test_ping_keepalive() {
+    let server = create_server_with_config(ServerConfig::default().idle_timeout(Some(Duration::from_millis(200))));
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    for _ in 0..5 {
+        thread::sleep(Duration::from_millis(100));
+        let round_trip = client.ping().expect("Ping was not answered");
+        assert!(round_trip < Duration::from_secs(1), "Implausible round-trip time: {:?}", round_trip);
+    }
+
+    // Without pings the connection is closed
+    thread::sleep(Duration::from_millis(400));
+    assert!(client.ping().is_err(), "Server did not close the idle connection");
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Builds a notification pushed outside of any request
+fn notification(kind: &str) -> ServerMessage {
+    ServerMessage {
+        message: Some(server_message::Message::Notification(Notification {
+            kind: kind.to_string(),
+            payload: kind.as_bytes().to_vec(),
+        })),
+        request_id: 0,
+    }
+}
+
+// Receives the next message and verifies it is the expected notification
+fn expect_notification(client: &mut Client, kind: &str) {
+    match client.receive().expect("Failed to receive notification") {
+        ServerMessage {
+            message: Some(server_message::Message::Notification(notification)),
+            request_id: 0,
+        } => assert_eq!(notification.kind, kind),
+        other => panic!("Expected Notification, but received {:?}", other),
+    }
+}
+
+// Test: Push notifications to every client or to a single one
+#[test]
+fn test_broadcast_and_send_to() {
+    let server = create_server_with_config(ServerConfig::default());
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut clients: Vec<_> = (0..3)
+        .map(|_| {
+            let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+            assert!(client.connect().is_ok(), "Failed to connect to the server");
+            send_and_receive_message(&mut client, client_message::Message::EchoMessage(EchoMessage::default()), Some(""));
+            client
+        })
+        .collect();
+    assert!(wait_for_connection_count(&server, 3), "Server did not count the connected clients");
+
+    assert_eq!(server.broadcast(&notification("everyone")), 3);
+    for client in clients.iter_mut() {
+        expect_notification(client, "everyone");
+    }
+
+    // Address a single client by the id listed for its address
+    let target = clients[1].local_addr().unwrap();
+    let id = server.connections().iter().find(|info| info.peer_addr == target).expect("Client is not listed").id;
+    server.send_to(id, &notification("just you")).expect("Failed to push to the client");
+    expect_notification(&mut clients[1], "just you");
+
+    // Pushed messages don't disturb request handling
+    send_and_receive_message(&mut clients[1], client_message::Message::EchoMessage(EchoMessage::default()), Some(""));
+    send_and_receive_message(&mut clients[0], client_message::Message::EchoMessage(EchoMessage::default()), Some(""));
+
+    let missing = server.send_to(u64::MAX, &notification("nobody"));
+    assert_eq!(missing.unwrap_err().kind(), io::ErrorKind::NotFound);
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Receives the next message and verifies it is an event with the expected topic and payload
+fn expect_event(client: &mut Client, topic: &str, payload: &[u8]) {
+    match client.receive().expect("Failed to receive event").message {
+        Some(server_message::Message::Event(event)) => {
+            assert_eq!(event.topic, topic);
+            assert_eq!(event.payload, payload);
+        }
+        other => panic!("Expected Event, but received {:?}", other),
+    }
+}
+
+// Test: Deliver published events to the subscribers of their topic only
+#[test]
+fn test_publish_subscribe() {
+    let server = create_server_with_config(ServerConfig::default());
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut clients: Vec<_> = (0..3)
+        .map(|_| {
+            let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+            assert!(client.connect().is_ok(), "Failed to connect to the server");
+            client
+        })
+        .collect();
+
+    assert_eq!(clients[0].subscribe("sensors").unwrap(), ["sensors"]);
+    assert_eq!(clients[0].subscribe("alerts").unwrap(), ["alerts", "sensors"]);
+    assert_eq!(clients[1].subscribe("sensors").unwrap(), ["sensors"]);
+
+    // The publisher isn't subscribed, so only the two subscribers get the event
+    assert_eq!(clients[2].publish("sensors", b"21.5").unwrap(), 2);
+    expect_event(&mut clients[0], "sensors", b"21.5");
+    expect_event(&mut clients[1], "sensors", b"21.5");
+    assert_eq!(clients[1].publish("alerts", b"overheat").unwrap(), 1);
+    expect_event(&mut clients[0], "alerts", b"overheat");
+    assert_eq!(clients[0].publish("unknown", b"").unwrap(), 0);
+
+    assert_eq!(clients[0].unsubscribe("sensors").unwrap(), ["alerts"]);
+    assert_eq!(clients[2].publish("sensors", b"22.0").unwrap(), 1);
+    expect_event(&mut clients[1], "sensors", b"22.0");
+
+    // Events don't get in the way of responses
+    send_and_receive_message(&mut clients[0], client_message::Message::EchoMessage(EchoMessage::default()), Some(""));
+
+    // Topics must be named
+    let error = clients[2].subscribe("").unwrap_err();
+    assert_eq!(error.error_code(), Some(ErrorCode::MalformedRequest));
+
+    // Subscriptions are listed with the connection and go away with it
+    let listed: Vec<_> = server.connections().into_iter().map(|info| info.subscriptions).collect();
+    assert_eq!(listed, [vec!["alerts".to_string()], vec!["sensors".to_string()], vec![]]);
+    assert!(clients[1].disconnect().is_ok(), "Failed to disconnect from the server");
+    assert!(wait_for_connection_count(&server, 2), "Server kept the disconnected client");
+    assert_eq!(clients[2].publish("sensors", b"22.5").unwrap(), 0);
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Limit how many topics a connection may subscribe to and how long topics may be
+#[test]
+fn test_subscription_limits() {
+    let server = create_server_with_config(ServerConfig::default().max_subscriptions(2).max_topic_length(8));
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert_eq!(client.subscribe("sensors").unwrap(), ["sensors"]);
+    assert_eq!(client.subscribe("alerts").unwrap(), ["alerts", "sensors"]);
+    let error = client.subscribe("status").unwrap_err();
+    assert_eq!(error.error_code(), Some(ErrorCode::TooManySubscriptions));
+
+    // Subscribing again to a topic takes no more room, and unsubscribing makes room
+    assert_eq!(client.subscribe("alerts").unwrap(), ["alerts", "sensors"]);
+    assert_eq!(client.unsubscribe("alerts").unwrap(), ["sensors"]);
+    assert_eq!(client.subscribe("status").unwrap(), ["sensors", "status"]);
+
+    // Topics longer than the limit are refused whatever the request
+    let error = client.subscribe("overlong!").unwrap_err();
+    assert_eq!(error.error_code(), Some(ErrorCode::MalformedRequest));
+    let error = client.publish("overlong!", b"").unwrap_err();
+    assert_eq!(error.error_code(), Some(ErrorCode::MalformedRequest));
+    assert_eq!(client.publish("eight-ch", b"").unwrap(), 0);
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Hold a bounded number of events while awaiting responses, dropping the oldest
+#[test]
+fn test_held_events_are_bounded() {
+    let server = create_server_with_config(ServerConfig::default());
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let config = ClientConfig::default().read_timeout(Some(Duration::from_millis(200)));
+    let mut client = Client::with_config(Endpoint::tcp("127.0.0.1", port), config);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert_eq!(client.subscribe("news").unwrap(), ["news"]);
+    let mut publisher = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(publisher.connect().is_ok(), "Failed to connect to the server");
+    for i in 0..1100u32 {
+        assert_eq!(publisher.publish("news", i.to_be_bytes()).unwrap(), 1);
+    }
+
+    // The echo is answered after every event, leaving only the newest 1024 held
+    assert_eq!(client.echo("after events").expect("Failed to echo"), "after events");
+    for i in 76..1100u32 {
+        match client.receive().expect("Failed to receive event").message {
+            Some(server_message::Message::Event(event)) => assert_eq!(event.payload, i.to_be_bytes()),
+            other => panic!("Expected Event, but received {:?}", other),
+        }
+    }
+    assert!(matches!(client.receive(), Err(ClientError::TimedOut)), "Events beyond the cap were held");
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Sends an AuthRequest with the given credentials and returns the response to it
+fn authenticate(
+    client: &mut Client,
+    credentials: Option<auth_request::Credentials>,
+) -> Result<AuthResponse, ClientError> {
+    match client.request(client_message::Message::AuthRequest(AuthRequest { credentials }))? {
+        server_message::Message::AuthResponse(response) => Ok(response),
+        other => panic!("Expected AuthResponse, but received {:?}", other),
+    }
+}
+
+// Test: Serve a client only once it has presented the pre-shared token
+#[test]
+fn test_token_authentication() {
+    let config = ServerConfig::default().auth(AuthMethod::Token(b"secret token".to_vec()));
+    let server = create_server_with_config(config);
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    // Requests are refused until the client authenticates, but pings are answered
+    let echo = client_message::Message::EchoMessage(EchoMessage { content: "early".to_string() });
+    client.send_request(echo).expect("Failed to send message");
+    expect_error_response(&mut client, ErrorCode::Unauthenticated);
+    assert!(client.ping().is_ok(), "Ping was not answered before authentication");
+
+    let error = client.authenticate(&AuthMethod::Token(b"wrong token".to_vec())).unwrap_err();
+    assert_eq!(error.error_code(), Some(ErrorCode::AuthenticationFailed));
+
+    assert!(client.authenticate(&AuthMethod::Token(b"secret token".to_vec())).is_ok(), "Valid token was refused");
+    let echo = client_message::Message::EchoMessage(EchoMessage { content: "authenticated".to_string() });
+    send_and_receive_message(&mut client, echo, Some("authenticated"));
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Authenticate with an HMAC of a server-issued challenge, which can only be answered once
+#[test]
+fn test_hmac_authentication() {
+    let secret = b"shared secret";
+    let config = ServerConfig::default().auth(AuthMethod::Hmac(secret.to_vec()));
+    let server = create_server_with_config(config);
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let request_challenge = |client: &mut Client| {
+        let response = authenticate(client, None).expect("Failed to request a challenge");
+        assert!(!response.authenticated);
+        assert_eq!(response.challenge.len(), 32, "Unexpected challenge size");
+        response.challenge
+    };
+
+    // A response computed with the wrong secret fails and uses up the challenge
+    let challenge = request_challenge(&mut client);
+    let forged = auth_request::Credentials::ChallengeResponse(auth::challenge_response(b"guess", &challenge));
+    assert!(matches!(authenticate(&mut client, Some(forged)), Err(ClientError::Server(_))));
+    let replayed = auth_request::Credentials::ChallengeResponse(auth::challenge_response(secret, &challenge));
+    assert!(matches!(authenticate(&mut client, Some(replayed)), Err(ClientError::Server(_))));
+
+    let mut other = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(other.connect().is_ok(), "Failed to connect to the server");
+    let challenge = request_challenge(&mut other);
+    assert_ne!(request_challenge(&mut client), challenge, "Challenges are not random");
+    let response = auth_request::Credentials::ChallengeResponse(auth::challenge_response(secret, &challenge));
+    assert!(authenticate(&mut other, Some(response)).expect("Valid response was refused").authenticated);
+    let add = client_message::Message::AddRequest(AddRequest { a: 1, b: 2, ..Default::default() });
+    send_and_receive_message(&mut other, add, Some("3"));
+
+    // The client answers the challenge itself when given the secret
+    assert!(client.authenticate(&AuthMethod::Hmac(secret.to_vec())).is_ok(), "Valid secret was refused");
+    assert_eq!(client.add(1, 2).expect("Failed to add"), 3);
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Close the connection once a client has failed to authenticate too many times, has
+// sent too many requests before authenticating, or has taken too long to authenticate
+#[test]
+fn test_authentication_failure_limit() {
+    let config = ServerConfig::default()
+        .auth(AuthMethod::Token(b"secret token".to_vec()))
+        .max_auth_failures(2)
+        .auth_timeout(Some(Duration::from_millis(300)));
+    let server = create_server_with_config(config);
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    for _ in 0..2 {
+        let error = client.authenticate(&AuthMethod::Token(b"wrong token".to_vec())).unwrap_err();
+        assert_eq!(error.error_code(), Some(ErrorCode::AuthenticationFailed));
+    }
+    assert!(client.receive().is_err(), "Server did not close the connection");
+
+    // Refused requests count as failed attempts
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    for _ in 0..2 {
+        let error = client.echo("unauthenticated").unwrap_err();
+        assert_eq!(error.error_code(), Some(ErrorCode::Unauthenticated));
+    }
+    assert!(client.receive().is_err(), "Server kept refusing requests from the connection");
+
+    // Pings keep a connection from going idle, but not past the authentication deadline
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let started = std::time::Instant::now();
+    while client.ping().is_ok() {
+        assert!(started.elapsed() < Duration::from_secs(5), "Server kept the unauthenticated connection");
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert!(started.elapsed() >= Duration::from_millis(300), "Connection was closed before its deadline");
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Sends an echo request and returns how long the server asks the client to wait, or `None`
+// if the request was answered
+fn echo_retry_after(client: &mut Client) -> Option<Duration> {
+    match client.echo("flood") {
+        Ok(_) => None,
+        Err(error) => Some(error.retry_after().unwrap_or_else(|| panic!("Unexpected error: {}", error))),
+    }
+}
+
+// Test: Throttle a client sending faster than its rate limit without slowing other clients
+#[test]
+fn test_rate_limit_per_connection() {
+    let config = ServerConfig::default().rate_limit(RateLimit::per_second(5).burst(3));
+    let server = create_server_with_config(config);
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut flooder = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(flooder.connect().is_ok(), "Failed to connect to the server");
+    let mut other = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(other.connect().is_ok(), "Failed to connect to the server");
+
+    // The burst is answered, then requests are refused with a hint of when to retry
+    for _ in 0..3 {
+        assert_eq!(echo_retry_after(&mut flooder), None, "Request within the burst was throttled");
+    }
+    let retry_after = echo_retry_after(&mut flooder).expect("Request beyond the burst was answered");
+    assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_millis(200), "Unexpected hint {:?}", retry_after);
+    assert!(echo_retry_after(&mut flooder).is_some(), "Request beyond the burst was answered");
+
+    assert_eq!(echo_retry_after(&mut other), None, "Other client was throttled");
+
+    // Waiting as told lets the next request through
+    thread::sleep(retry_after);
+    assert_eq!(echo_retry_after(&mut flooder), None, "Request after the hint was throttled");
+
+    // The worker records failures before reading the next request, so both are counted
+    let address = flooder.local_addr().unwrap();
+    let info = server.connections().into_iter().find(|info| info.peer_addr == address).expect("Client is not listed");
+    assert_eq!(info.failed_requests, 2, "Throttled requests were not counted as failed");
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Share a rate limit between every connection from the same IP address
+#[test]
+fn test_rate_limit_per_peer() {
+    let config = ServerConfig::default().peer_rate_limit(RateLimit::per_second(1).burst(2));
+    let server = create_server_with_config(config);
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut clients: Vec<_> = (0..3)
+        .map(|_| {
+            let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+            assert!(client.connect().is_ok(), "Failed to connect to the server");
+            client
+        })
+        .collect();
+    assert_eq!(echo_retry_after(&mut clients[0]), None);
+    assert_eq!(echo_retry_after(&mut clients[1]), None);
+    let retry_after = echo_retry_after(&mut clients[2]).expect("Third request from the same IP was answered");
+    assert!(retry_after <= Duration::from_secs(1), "Unexpected hint {:?}", retry_after);
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Reject rate limits that would never let a request through
+#[test]
+fn test_rate_limit_validation() {
+    let result = Server::with_config(ServerConfig::default().rate_limit(RateLimit::per_second(0)));
+    assert_eq!(result.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidInput));
+    let result = Server::with_config(ServerConfig::default().peer_rate_limit(RateLimit::per_second(10).burst(0)));
+    assert_eq!(result.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidInput));
+}
+
+// Test: Report client failures as typed errors, keeping the connection after a read timeout
+// but not after a write timeout
+#[test]
+fn test_client_errors() {
+    let server = create_server_with_config(ServerConfig::default());
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(matches!(client.receive(), Err(ClientError::NotConnected)));
+    assert!(matches!(client.disconnect(), Err(ClientError::NotConnected)));
+
+    // A timed out receive leaves the connection usable
+    let config = ClientConfig::default().read_timeout(Some(Duration::from_millis(100)));
+    let mut client = Client::with_config(Endpoint::tcp("127.0.0.1", port), config);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert!(matches!(client.receive(), Err(ClientError::TimedOut)));
+    assert!(client.is_connected(), "Client dropped the connection after a timeout");
+    let message = client_message::Message::EchoMessage(EchoMessage { content: "after timeout".to_string() });
+    send_and_receive_message(&mut client, message, Some("after timeout"));
+
+    // A timed out send may have written part of a message, so the connection is closed. The
+    // listener never accepts, so nothing reads what the client writes.
+    let silent = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+    let silent_port = silent.local_addr().unwrap().port();
+    let config = ClientConfig::default().write_timeout(Some(Duration::from_millis(100)));
+    let mut client = Client::with_config(Endpoint::tcp("127.0.0.1", silent_port), config);
+    assert!(client.connect().is_ok(), "Failed to connect to the listener");
+    assert!(matches!(client.send_raw(&vec![0; 64 << 20]), Err(ClientError::TimedOut)));
+    assert!(!client.is_connected(), "Client kept a connection after a partial write");
+    drop(silent);
+
+    // A message larger than the client accepts is a protocol error that closes the connection
+    let mut client = Client::with_config(Endpoint::tcp("127.0.0.1", port), ClientConfig::default().max_message_size(16));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    let message = client_message::Message::EchoMessage(EchoMessage { content: "x".repeat(64) });
+    client.send_request(message).expect("Failed to send message");
+    assert!(matches!(client.receive(), Err(ClientError::Protocol(_))));
+    assert!(!client.is_connected(), "Client kept a connection that is out of sync");
+
+    // A server going away is reported as a disconnection
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert!(client.ping().is_ok(), "Ping was not answered");
+    server.stop();
+    handle.join().unwrap();
+    expect_goodbye(&mut client);
+    assert!(matches!(client.receive(), Err(ClientError::Disconnected)));
+    drop(server); // Closes the listener
+    assert!(matches!(client.connect(), Err(ClientError::Io(_))), "Connected to a stopped server");
+}
+
+// Handler answering every AddRequest with an EchoMessage
+struct WrongAnswerHandler;
+
+impl Handler for WrongAnswerHandler {
+    fn handle(
+        &self,
+        request: &client_message::Message,
+        _context: &ConnectionContext,
+    ) -> Option<server_message::Message> {
+        match request {
+            client_message::Message::AddRequest(_) => Some(server_message::Message::EchoMessage(EchoMessage::default())),
+            _ => None,
+        }
+    }
+}
+
+// Test: Send requests through the typed client methods, getting error and mismatched
+// responses back as typed errors
+#[test]
+fn test_typed_requests() {
+    let server = create_server_with_config(ServerConfig::default());
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(matches!(client.echo("offline"), Err(ClientError::NotConnected)));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+
+    assert_eq!(client.echo("Hello, World!").unwrap(), "Hello, World!");
+    assert_eq!(client.add(40, 2).unwrap(), 42);
+    assert_eq!(client.add_with_mode(i32::MAX, 1, OverflowMode::Saturating).unwrap(), i32::MAX);
+    assert_eq!(client.add_with_mode(i32::MAX, 1, OverflowMode::Wrapping).unwrap(), i32::MIN);
+
+    // Error responses become typed errors and leave the connection usable
+    let error = client.add(i32::MAX, 1).unwrap_err();
+    assert!(matches!(error, ClientError::Server(_)), "Unexpected error: {}", error);
+    assert_eq!(error.error_code(), Some(ErrorCode::ArithmeticOverflow));
+    assert!(client.is_connected(), "Client dropped the connection after an error response");
+
+    // Authenticating with a server that doesn't require it succeeds at once
+    assert!(client.authenticate(&AuthMethod::Hmac(b"unused".to_vec())).is_ok());
+    assert_eq!(client.echo("still here").unwrap(), "still here");
+    server.stop();
+    handle.join().unwrap();
+
+    // Responses of the wrong kind are reported rather than mistaken for the expected one
+    let server = Arc::new(Server::new().expect("Failed to start server").with_handler(WrongAnswerHandler));
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    match client.add(1, 2) {
+        Err(ClientError::UnexpectedResponse(Some(server_message::Message::EchoMessage(_)))) => {}
+        other => panic!("Expected UnexpectedResponse, but received {:?}", other),
+    }
+    assert_eq!(client.echo("in sync").unwrap(), "in sync");
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Re-establish a lost connection with backoff, restoring authentication and
+// subscriptions, and resend idempotent requests only
+#[test]
+fn test_client_reconnect() {
+    let port = find_available_port();
+    let token = AuthMethod::Token(b"secret token".to_vec());
+    let config = ServerConfig::default().port(port).auth(token.clone());
+    let start_server = move || {
+        let server = create_server_with_config(config.clone());
+        let handle = setup_server_thread(server.clone());
+        (server, handle)
+    };
+    let stop_server = |(server, handle): (Arc<Server>, JoinHandle<()>)| {
+        server.stop();
+        handle.join().unwrap();
+    };
+
+    let reconnects = Arc::new(Mutex::new(Vec::new()));
+    let recorded = reconnects.clone();
+    let policy = ReconnectPolicy::default()
+        .initial_delay(Duration::from_millis(20))
+        .max_delay(Duration::from_millis(100))
+        .max_attempts(Some(10))
+        .retry_idempotent(true)
+        .on_reconnect(move |_, attempts| recorded.lock().unwrap().push(attempts));
+    let mut client = Client::with_config(Endpoint::tcp("127.0.0.1", port), ClientConfig::default().reconnect(policy));
+
+    let server = start_server();
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert!(client.authenticate(&token).is_ok(), "Valid token was refused");
+    assert_eq!(client.subscribe("news").unwrap(), ["news"]);
+
+    // The server comes back after a while; the echo is sent again once it has
+    stop_server(server);
+    let restart = start_server.clone();
+    let restarter = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(200));
+        restart()
+    });
+    assert_eq!(client.echo("after restart").expect("Echo was not retried"), "after restart");
+    let server = restarter.join().unwrap();
+    let attempts = reconnects.lock().unwrap().clone();
+    assert!(matches!(attempts[..], [n] if n > 1), "Unexpected reconnects {:?}", attempts);
+
+    // The new connection is authenticated and subscribed like the lost one
+    let mut publisher = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(publisher.connect().is_ok(), "Failed to connect to the server");
+    assert!(publisher.authenticate(&token).is_ok(), "Valid token was refused");
+    assert_eq!(publisher.publish("news", b"restored").unwrap(), 1);
+    expect_event(&mut client, "news", b"restored");
+
+    // Publishing isn't idempotent, so losing the connection fails it; the next request
+    // reconnects
+    stop_server(server);
+    let server = start_server();
+    assert!(client.publish("news", b"lost").is_err(), "Publish was sent again");
+    assert_eq!(client.add(1, 2).expect("Failed to reconnect"), 3);
+    assert_eq!(reconnects.lock().unwrap().len(), 2);
+
+    // Once the attempts run out the error of the last one is returned
+    stop_server(server);
+    assert!(matches!(client.echo("gone"), Err(ClientError::Io(_))), "Reconnected to a stopped server");
+    assert!(!client.is_connected());
+    assert_eq!(reconnects.lock().unwrap().len(), 2);
+}
+
+// Test: Grow the wait between reconnect attempts up to the limit, skipping at most the
+// jitter fraction of it, with out of range jitter clamped and NaN meaning none
+#[test]
+fn test_reconnect_delay() {
+    let policy = ReconnectPolicy::default()
+        .initial_delay(Duration::from_millis(100))
+        .max_delay(Duration::from_millis(500))
+        .multiplier(2.0);
+    let waits = [100, 200, 400, 500, 500].map(Duration::from_millis);
+
+    for jitter in [f64::NAN, 0.0, -1.0, f64::NEG_INFINITY] {
+        let policy = policy.clone().jitter(jitter);
+        for (attempts, wait) in (1..).zip(waits) {
+            assert_eq!(policy.delay(attempts), wait, "Jitter {} skipped part of the wait", jitter);
+        }
+    }
+    for jitter in [0.5, 1.0, 2.0, f64::INFINITY] {
+        let policy = policy.clone().jitter(jitter);
+        let skippable = jitter.min(1.0);
+        for (attempts, wait) in (1..).zip(waits) {
+            let delay = policy.delay(attempts);
+            assert!(delay <= wait && delay >= wait.mul_f64(1.0 - skippable), "Jitter {} gave {:?}", jitter, delay);
+        }
+    }
+}
+
+// Test: Connect to the first endpoint that accepts and fail over to the next one when the
+// connection is lost, tracking the health of each
+#[test]
+fn test_client_failover() {
+    let start_server = || {
+        let server = create_server_with_config(ServerConfig::default());
+        let port = server.get_port().expect("Failed to retrieve server port");
+        let handle = setup_server_thread(server.clone());
+        (server, handle, port)
+    };
+    let (primary, primary_handle, primary_port) = start_server();
+    let (secondary, secondary_handle, secondary_port) = start_server();
+    let endpoints = [
+        Endpoint::tcp("127.0.0.1", 0), // Connections to port 0 are always refused
+        Endpoint::tcp("127.0.0.1", primary_port),
+        Endpoint::tcp("localhost", secondary_port),
+    ];
+    let policy = ReconnectPolicy::default().initial_delay(Duration::from_millis(10)).max_attempts(Some(2));
+    let config = ClientConfig::default().reconnect(policy.retry_idempotent(true));
+    let mut client = Client::with_endpoints(endpoints.clone(), config);
+
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert_eq!(client.endpoint(), &endpoints[1], "Connected to the wrong endpoint");
+    let health = client.health();
+    assert_eq!(health[0].consecutive_failures, 1);
+    assert!(health[0].last_error.is_some(), "Failure was not recorded");
+    assert!(health[1].is_healthy() && health[1].last_connected.is_some());
+    assert_eq!(health[2].last_connected, None, "Later endpoint was connected to");
+
+    // Losing the primary fails over to the endpoint that hasn't failed yet
+    primary.stop();
+    primary_handle.join().unwrap();
+    drop(primary); // Closes the listener
+    assert_eq!(client.echo("failed over").expect("Echo was not retried"), "failed over");
+    assert_eq!(client.endpoint(), &endpoints[2], "Client did not fail over");
+    let health = client.health();
+    assert_eq!(health[1].consecutive_failures, 1, "Lost connection was not recorded");
+    assert!(health[2].is_healthy() && health[2].last_connected.is_some());
+
+    // With every server gone the client gives up and no endpoint is healthy
+    secondary.stop();
+    secondary_handle.join().unwrap();
+    drop(secondary);
+    assert!(client.echo("gone").is_err(), "Echo was answered with every server gone");
+    assert!(client.health().iter().all(|health| !health.is_healthy()), "Unexpected health {:?}", client.health());
+}
+
+// Test: Share one connection between threads, routing each response to the thread that
+// sent the request and everything else to `receive`
+#[test]
+fn test_shared_client() {
+    let server = create_server_with_config(ServerConfig::default());
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    // The subscription made before sharing carries over
+    let mut client = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert_eq!(client.subscribe("news").unwrap(), ["news"]);
+    let client = client.into_shared().expect("Failed to share the connection");
+
+    let workers: Vec<_> = (0..8)
+        .map(|worker| {
+            let client = client.clone();
+            thread::spawn(move || {
+                for i in 0..50 {
+                    let content = format!("worker {} message {}", worker, i);
+                    assert_eq!(client.echo(content.as_str()).expect("Failed to echo"), content);
+                    assert_eq!(client.add(worker, i).expect("Failed to add"), worker + i);
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().expect("Worker received a wrong response");
+    }
+    assert_eq!(server.connections().len(), 1, "Threads did not share the connection");
+
+    let mut publisher = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(publisher.connect().is_ok(), "Failed to connect to the server");
+    assert_eq!(publisher.publish("news", b"shared").unwrap(), 1);
+    match client.receive().expect("Failed to receive event").message {
+        Some(server_message::Message::Event(event)) => assert_eq!(event.payload, b"shared"),
+        other => panic!("Expected Event, but received {:?}", other),
+    }
+
+    // Once the server is gone every handle fails
+    server.stop();
+    handle.join().unwrap();
+    match client.receive().expect("Failed to receive goodbye").message {
+        Some(server_message::Message::Goodbye(_)) => {}
+        other => panic!("Expected Goodbye, but received {:?}", other),
+    }
+    assert!(client.clone().echo("gone").is_err(), "Echo was answered by a stopped server");
+    assert!(!client.is_connected());
+    drop(server); // Closes the listener
+    assert!(SharedClient::connect(Endpoint::tcp("127.0.0.1", port), ClientConfig::default()).is_err());
+}
+
+// Test: Hold a bounded number of events nobody receives, still answering requests once the
+// rest are dropped
+#[test]
+fn test_shared_client_unreceived_events() {
+    let server = create_server_with_config(ServerConfig::default());
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let config = ClientConfig::default().read_timeout(Some(Duration::from_millis(200)));
+    let client = SharedClient::connect(Endpoint::tcp("127.0.0.1", port), config).expect("Failed to connect");
+    assert_eq!(client.subscribe("news").unwrap(), ["news"]);
+    let mut publisher = Client::new(Endpoint::tcp("127.0.0.1", port));
+    assert!(publisher.connect().is_ok(), "Failed to connect to the server");
+    for i in 0..1100u32 {
+        assert_eq!(publisher.publish("news", i.to_be_bytes()).unwrap(), 1);
+    }
+
+    // The echo is answered after every event has been read
+    assert_eq!(client.echo("after events").expect("Failed to echo"), "after events");
+    for i in 0..1024u32 {
+        match client.receive().expect("Failed to receive event").message {
+            Some(server_message::Message::Event(event)) => assert_eq!(event.payload, i.to_be_bytes()),
+            other => panic!("Expected Event, but received {:?}", other),
+        }
+    }
+    assert!(matches!(client.receive(), Err(ClientError::TimedOut)), "Events beyond the cap were held");
+
+    // Once there is room again new events are held
+    assert_eq!(publisher.publish("news", b"later").unwrap(), 1);
+    match client.receive().expect("Failed to receive event").message {
+        Some(server_message::Message::Event(event)) => assert_eq!(event.payload, b"later"),
+        other => panic!("Expected Event, but received {:?}", other),
+    }
+
+    server.stop();
+    handle.join().unwrap();
+}
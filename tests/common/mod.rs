@@ -0,0 +1,36 @@
+// Fixtures shared by the integration tests. Each test crate uses only some of them.
+#![allow(dead_code)]
+
+use embedded_recruitment_task::{
+    client::Client,
+    config::ServerConfig,
+    message::{server_message, ErrorCode},
+    server::Server,
+};
+use std::{
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+// Spawns a new thread to run the server and returns the thread handle
+pub fn setup_server_thread(server: Arc<Server>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        server.run().expect("Server encountered an error");
+    })
+}
+
+// Creates a server instance with the given settings
+pub fn create_server_with_config(config: ServerConfig) -> Arc<Server> {
+    let server = Server::with_config(config).expect("Failed to start server with the given config");
+    Arc::new(server)
+}
+
+// Receives the next response and verifies it is an error with the expected code
+pub fn expect_error_response(client: &mut Client, expected_code: ErrorCode) {
+    match client.receive().expect("Failed to receive response").message {
+        Some(server_message::Message::ErrorResponse(error)) => {
+            assert_eq!(error.code(), expected_code, "Unexpected error code: {}", error.detail);
+        }
+        _ => panic!("Expected ErrorResponse, but received a different message"),
+    }
+}
@@ -0,0 +1,159 @@
+#![cfg(feature = "tls")]
+
+mod common;
+
+use common::{create_server_with_config, setup_server_thread};
+use embedded_recruitment_task::{
+    client::{Client, Endpoint},
+    config::{ClientConfig, ServerConfig},
+    server::Server,
+    tls::{ClientTlsConfig, TlsConfig},
+};
+use rcgen::{BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+use std::{
+    sync::Arc,
+    thread,
+};
+
+// Self-signed CA with a server certificate for "localhost" and a client certificate issued
+// by it, all in PEM
+struct Certificates {
+    ca: String,
+    server: (String, String), // Certificate and private key
+    client: (String, String), // Certificate and private key
+}
+
+// Issues a fresh set of certificates for a test
+fn generate_certificates() -> Certificates {
+    let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca_key = KeyPair::generate().unwrap();
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+
+    let issue = |name: &str, usage: ExtendedKeyUsagePurpose| {
+        let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+        params.extended_key_usages = vec![usage];
+        let key = KeyPair::generate().unwrap();
+        let certificate = params.signed_by(&key, &ca, &ca_key).unwrap();
+        (certificate.pem(), key.serialize_pem())
+    };
+
+    Certificates {
+        ca: ca.pem(),
+        server: issue("localhost", ExtendedKeyUsagePurpose::ServerAuth),
+        client: issue("test-client", ExtendedKeyUsagePurpose::ClientAuth),
+    }
+}
+
+// Creates a server presenting the given certificate
+fn create_tls_server(tls: TlsConfig) -> Arc<Server> {
+    create_server_with_config(ServerConfig::default().tls(tls))
+}
+
+// Creates a client that connects over TLS, verifying the server is "localhost"
+fn tls_client(port: u16, tls: ClientTlsConfig) -> Client {
+    let config = ClientConfig::default().tls(tls).server_name("localhost");
+    let mut client = Client::with_config(Endpoint::tcp("127.0.0.1", port), config);
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    client
+}
+
+// Test: Answer echo and add requests over TLS, including from threads sharing a connection
+#[test]
+fn test_tls_echo_and_add() {
+    let certificates = generate_certificates();
+    let (cert, key) = certificates.server;
+    let server = create_tls_server(TlsConfig::new(cert, key));
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let mut client = tls_client(port, ClientTlsConfig::new(certificates.ca));
+    assert_eq!(client.echo("Hello over TLS").expect("Failed to echo"), "Hello over TLS");
+    let large = "x".repeat(4096);
+    assert_eq!(client.echo(large.as_str()).expect("Failed to echo"), large);
+
+    assert_eq!(client.add(19, 23).expect("Failed to add"), 42);
+
+    // Requests from several threads share the session
+    let client = client.into_shared().expect("Failed to share the connection");
+    let workers: Vec<_> = (0..4)
+        .map(|worker| {
+            let client = client.clone();
+            thread::spawn(move || {
+                for i in 0..20 {
+                    assert_eq!(client.add(worker, i).expect("Failed to add"), worker + i);
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().expect("Worker received a wrong response");
+    }
+
+    assert!(client.disconnect().is_ok(), "Failed to disconnect from the server");
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: A server requiring client certificates serves clients presenting one and drops
+// clients that don't
+#[test]
+fn test_tls_client_authentication() {
+    let certificates = generate_certificates();
+    let (cert, key) = certificates.server;
+    let tls = TlsConfig::new(cert, key).require_client_auth(certificates.ca.clone());
+    let server = create_tls_server(tls);
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let (cert, key) = certificates.client;
+    let mut authenticated = tls_client(port, ClientTlsConfig::new(certificates.ca.clone()).with_identity(cert, key));
+    assert_eq!(authenticated.echo("authenticated").expect("Failed to echo"), "authenticated");
+
+    // The server only checks the client after the client considers the handshake done, so
+    // the failure may surface on either the request or the response
+    let mut anonymous = tls_client(port, ClientTlsConfig::new(certificates.ca));
+    assert!(anonymous.echo("anonymous").is_err(), "Server served a client without a certificate");
+
+    assert_eq!(authenticated.echo("still served").expect("Failed to echo"), "still served");
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: A client refuses a server whose certificate isn't issued by a root it trusts, and
+// the server keeps serving other clients
+#[test]
+fn test_tls_untrusted_server() {
+    let certificates = generate_certificates();
+    let (cert, key) = certificates.server;
+    let server = create_tls_server(TlsConfig::new(cert, key));
+    let port = server.get_port().expect("Failed to retrieve server port");
+    let handle = setup_server_thread(server.clone());
+
+    let other_ca = generate_certificates().ca;
+    let mut client = tls_client(port, ClientTlsConfig::new(other_ca));
+    assert!(client.echo("untrusted").is_err(), "Client accepted an untrusted certificate");
+
+    let mut trusting = tls_client(port, ClientTlsConfig::new(certificates.ca));
+    assert_eq!(trusting.echo("trusted").expect("Failed to echo"), "trusted");
+
+    server.stop();
+    handle.join().unwrap();
+}
+
+// Test: Unusable certificates are reported when the server is created
+#[test]
+fn test_tls_invalid_config() {
+    let certificates = generate_certificates();
+    let (cert, _) = certificates.server;
+    let result = Server::with_config(ServerConfig::default().tls(TlsConfig::new(cert, "not a key")));
+    assert!(result.is_err(), "Server accepted a TLS config without a private key");
+
+    #[cfg(feature = "async")]
+    {
+        let (cert, key) = certificates.client;
+        let config = ServerConfig::default().tls(TlsConfig::new(cert, key));
+        let result = embedded_recruitment_task::async_server::AsyncServer::with_config(config);
+        assert!(result.is_err(), "AsyncServer accepted a TLS config");
+    }
+}
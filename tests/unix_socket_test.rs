@@ -0,0 +1,89 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{create_server_with_config, setup_server_thread};
+use embedded_recruitment_task::{
+    client::{Client, Endpoint},
+    config::ServerConfig,
+    handler::Address,
+    message::server_message,
+    server::Server,
+};
+use std::{
+    fs, io,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    os::unix::net::UnixListener,
+    path::PathBuf,
+};
+
+// Returns a socket path unique to the test, with nothing left at it by an earlier run
+fn socket_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rust-server-{}-{}.sock", std::process::id(), name));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+// Test: Serve the protocol over a Unix domain socket with the configured permissions, and
+// remove the socket file once the server is gone
+#[test]
+fn test_unix_socket_echo_and_add() {
+    let path = socket_path("echo");
+    let config = ServerConfig::default().unix_socket(&path).unix_socket_mode(0o600);
+    let server = create_server_with_config(config);
+    let handle = setup_server_thread(server.clone());
+
+    let metadata = fs::metadata(&path).expect("Socket file was not created");
+    assert!(metadata.file_type().is_socket(), "Server did not create a socket");
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o600, "Socket permissions were not applied");
+    let leftovers = fs::read_dir(path.parent().unwrap())
+        .unwrap()
+        .filter_map(Result::ok)
+        .any(|entry| entry.file_name().to_string_lossy().starts_with(&format!(".socket-{}-", std::process::id())));
+    assert!(!leftovers, "Directory the socket was bound in was left behind");
+    assert_eq!(server.get_port().unwrap_err().kind(), io::ErrorKind::Unsupported);
+
+    let mut client = Client::new(Endpoint::unix(&path));
+    assert!(client.connect().is_ok(), "Failed to connect to the server");
+    assert_eq!(client.echo("Hello over a Unix socket").expect("Failed to echo"), "Hello over a Unix socket");
+
+    assert_eq!(client.add(2, 40).expect("Failed to add"), 42);
+
+    let connections = server.connections();
+    assert_eq!(connections.len(), 1);
+    assert_eq!(connections[0].peer_addr, Address::Unix(None), "Unexpected peer address");
+
+    // Stopping wakes the accept loop through the socket and says goodbye to the client
+    server.stop();
+    handle.join().unwrap();
+    match client.receive().expect("Failed to receive goodbye").message {
+        Some(server_message::Message::Goodbye(_)) => {}
+        _ => panic!("Expected Goodbye, but received a different message"),
+    }
+
+    drop(server);
+    assert!(!path.exists(), "Socket file was left behind");
+}
+
+// Test: Replace a socket file nobody listens on, but refuse to take over a live socket or
+// to delete a file that isn't a socket
+#[test]
+fn test_unix_socket_stale_cleanup() {
+    let path = socket_path("stale");
+
+    // A listener dropped without removing its file leaves a stale socket behind
+    drop(UnixListener::bind(&path).expect("Failed to create stale socket"));
+    assert!(path.exists());
+    let server = Server::with_config(ServerConfig::default().unix_socket(&path)).expect("Stale socket was not replaced");
+
+    let result = Server::with_config(ServerConfig::default().unix_socket(&path));
+    assert_eq!(result.err().map(|e| e.kind()), Some(io::ErrorKind::AddrInUse), "Live socket was taken over");
+    assert!(path.exists(), "Live socket was removed");
+    drop(server);
+
+    fs::write(&path, "not a socket").unwrap();
+    let result = Server::with_config(ServerConfig::default().unix_socket(&path));
+    assert_eq!(result.err().map(|e| e.kind()), Some(io::ErrorKind::AlreadyExists));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "not a socket", "Regular file was touched");
+    fs::remove_file(&path).unwrap();
+}